@@ -0,0 +1,38 @@
+#import bevy_pbr::{
+    pbr_functions,
+    forward_io::{Vertex, VertexOutput},
+}
+#import "billboard_facing.wgsl"::billboard_vertex
+
+struct BillboardMaterial {
+    color: vec4<f32>,
+    uv_rect: vec4<f32>,
+    alpha_cutoff: f32,
+};
+
+@group(2) @binding(0) var material_color_texture: texture_2d<f32>;
+@group(2) @binding(1) var material_color_sampler: sampler;
+@group(2) @binding(2) var<uniform> material: BillboardMaterial;
+
+// Facing comes from the mesh tag, like every other billboard.
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    return billboard_vertex(vertex);
+}
+
+@fragment
+fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef VISIBILITY_RANGE_DITHER
+    pbr_functions::visibility_range_dither(mesh.position, mesh.visibility_range_dither);
+#endif
+
+    var color = material.color;
+#ifdef VERTEX_UVS_A
+    let uv = mesh.uv * material.uv_rect.zw + material.uv_rect.xy;
+    color *= textureSample(material_color_texture, material_color_sampler, uv);
+#endif
+    if color.a < material.alpha_cutoff {
+        discard;
+    }
+    return color;
+}
@@ -0,0 +1,86 @@
+#import bevy_pbr::{
+    mesh_functions,
+    mesh_view_bindings::view,
+    forward_io::{Vertex, VertexOutput},
+    view_transformations::position_world_to_clip,
+}
+
+const TAU: f32 = 6.28318530718;
+
+// Billboards carry their facing mode and spin in the mesh tag (see
+// `billboard::facing_tag`), so every view can turn them towards itself.
+// Only the translation and scale of the model matrix are used for them.
+fn billboard_position(world_from_local: mat4x4<f32>, position: vec3<f32>, tag: u32) -> vec3<f32> {
+    let center = world_from_local[3].xyz;
+    let scale = vec2<f32>(length(world_from_local[0].xyz), length(world_from_local[1].xyz));
+    let spin = f32(tag >> 16u) / 65536.0 * TAU;
+
+    let local = position.xy * scale;
+    let corner = vec2<f32>(
+        local.x * cos(spin) - local.y * sin(spin),
+        local.x * sin(spin) + local.y * cos(spin),
+    );
+
+    var right = view.world_from_view[0].xyz;
+    var up = view.world_from_view[1].xyz;
+    let mode = tag & 3u;
+    if mode == 2u {
+        right = normalize(vec3<f32>(right.x, 0.0, right.z));
+        up = vec3<f32>(0.0, 1.0, 0.0);
+    } else if mode == 3u {
+        let to_cam = view.world_position - center;
+        let side = cross(vec3<f32>(0.0, 1.0, 0.0), to_cam);
+        // Straight above or below there is no side to pick, so keep the
+        // view plane's axes.
+        if dot(side, side) > 1e-8 {
+            right = normalize(side);
+            up = normalize(cross(to_cam, right));
+        }
+    }
+
+    return center + right * corner.x + up * corner.y;
+}
+
+// The mesh vertex shader, except that tagged billboards face the view.
+fn billboard_vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+
+    let world_from_local = mesh_functions::get_world_from_local(vertex.instance_index);
+    let tag = mesh_functions::get_tag(vertex.instance_index);
+
+#ifdef VERTEX_POSITIONS
+    if (tag & 3u) == 0u {
+        out.world_position = mesh_functions::mesh_position_local_to_world(
+            world_from_local, vec4<f32>(vertex.position, 1.0));
+    } else {
+        out.world_position = vec4<f32>(
+            billboard_position(world_from_local, vertex.position, tag), 1.0);
+    }
+    out.position = position_world_to_clip(out.world_position.xyz);
+#endif
+#ifdef VERTEX_NORMALS
+    out.world_normal = mesh_functions::mesh_normal_local_to_world(
+        vertex.normal, vertex.instance_index);
+#endif
+#ifdef VERTEX_UVS_A
+    out.uv = vertex.uv;
+#endif
+#ifdef VERTEX_UVS_B
+    out.uv_b = vertex.uv_b;
+#endif
+#ifdef VERTEX_TANGENTS
+    out.world_tangent = mesh_functions::mesh_tangent_local_to_world(
+        world_from_local, vertex.tangent, vertex.instance_index);
+#endif
+#ifdef VERTEX_COLORS
+    out.color = vertex.color;
+#endif
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    out.instance_index = vertex.instance_index;
+#endif
+#ifdef VISIBILITY_RANGE_DITHER
+    out.visibility_range_dither = mesh_functions::get_visibility_range_dither_level(
+        vertex.instance_index, world_from_local[3]);
+#endif
+    return out;
+}
@@ -1,17 +1,40 @@
-#import bevy_pbr::forward_io::VertexOutput
+#import bevy_pbr::{
+    pbr_functions,
+    forward_io::{Vertex, VertexOutput},
+}
+#import "billboard_facing.wgsl"::billboard_vertex
+
+struct FlatMaterial {
+    color: vec4<f32>,
+    uv_rect: vec4<f32>,
+    alpha_cutoff: f32,
+};
 
 @group(2) @binding(0) var material_color_texture: texture_2d<f32>;
 @group(2) @binding(1) var material_color_sampler: sampler;
-@group(2) @binding(2) var<uniform> material_color: vec4<f32>;
+@group(2) @binding(2) var<uniform> material: FlatMaterial;
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    return billboard_vertex(vertex);
+}
 
 @fragment
 fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {
-    var color = material_color;
+#ifdef VISIBILITY_RANGE_DITHER
+    pbr_functions::visibility_range_dither(mesh.position, mesh.visibility_range_dither);
+#endif
+
+    var color = material.color;
 #ifdef VERTEX_UVS_A
-    color *= textureSample(material_color_texture, material_color_sampler, mesh.uv);
+    let uv = mesh.uv * material.uv_rect.zw + material.uv_rect.xy;
+    color *= textureSample(material_color_texture, material_color_sampler, uv);
 #endif
 #ifdef VERTEX_COLORS
     color *= mesh.color;
 #endif
+    if color.a < material.alpha_cutoff {
+        discard;
+    }
     return color;
-}
\ No newline at end of file
+}
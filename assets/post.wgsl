@@ -0,0 +1,186 @@
+#import bevy_sprite::mesh2d_vertex_output::VertexOutput
+
+// Must match the pass ids in `post.rs`.
+const PASS_QUANTIZE: u32 = 1u;
+const PASS_CRT: u32 = 2u;
+const PASS_VIGNETTE: u32 = 3u;
+const PASS_FADE: u32 = 4u;
+const PASS_ABERRATION: u32 = 5u;
+
+const TAU: f32 = 6.28318530718;
+
+struct Post {
+    // Passes in the order they run, 0 after the last one.
+    order: array<vec4<u32>, 2>,
+    levels: u32,
+    // Colors in the palette texture, 0 without one.
+    palette_size: u32,
+    // 0: off, 1: bayer 4x4, 2: bayer 8x8, 3: noise
+    dither: u32,
+    dither_strength: f32,
+    scanlines: f32,
+    curvature: f32,
+    aberration: f32,
+    vignette_strength: f32,
+    vignette_radius: f32,
+    fade_amount: f32,
+    fade_color: vec4<f32>,
+};
+
+@group(2) @binding(0) var material_color_texture: texture_2d<f32>;
+@group(2) @binding(1) var material_color_sampler: sampler;
+// Everything is packed in one uniform struct, lone scalars are too small for WebGL2.
+@group(2) @binding(2) var<uniform> post: Post;
+@group(2) @binding(3) var palette_texture: texture_2d<f32>;
+
+fn bayer2(p: vec2<u32>) -> u32 {
+    return (p.x ^ p.y) * 2u + p.y;
+}
+
+// Thresholds in [0, 1) built up from the 2x2 matrix.
+fn bayer4(p: vec2<u32>) -> f32 {
+    let v = bayer2(p & vec2<u32>(1u)) * 4u + bayer2((p >> vec2<u32>(1u)) & vec2<u32>(1u));
+    return (f32(v) + 0.5) / 16.0;
+}
+
+fn bayer8(p: vec2<u32>) -> f32 {
+    let v = bayer2(p & vec2<u32>(1u)) * 16u
+        + bayer2((p >> vec2<u32>(1u)) & vec2<u32>(1u)) * 4u
+        + bayer2((p >> vec2<u32>(2u)) & vec2<u32>(1u));
+    return (f32(v) + 0.5) / 64.0;
+}
+
+// Interleaved gradient noise, a cheap stand-in for a blue noise texture.
+fn noise(p: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(p, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+fn threshold(pixel: vec2<f32>) -> f32 {
+    let p = vec2<u32>(pixel);
+    switch post.dither {
+        case 1u: { return bayer4(p); }
+        case 2u: { return bayer8(p); }
+        case 3u: { return noise(floor(pixel)); }
+        default: { return 0.5; }
+    }
+}
+
+fn nearest_in_palette(color: vec3<f32>) -> vec3<f32> {
+    // Compare roughly in gamma space so dark colors aren't all lumped together.
+    let wanted = sqrt(color);
+    var best = textureLoad(palette_texture, vec2<i32>(0, 0), 0).rgb;
+    var best_dist = 1e9;
+    for (var i = 0u; i < post.palette_size; i++) {
+        let entry = textureLoad(palette_texture, vec2<i32>(i32(i), 0), 0).rgb;
+        let diff = sqrt(entry) - wanted;
+        let dist = dot(diff, diff);
+        if dist < best_dist {
+            best_dist = dist;
+            best = entry;
+        }
+    }
+    return best;
+}
+
+fn quantize(color: vec3<f32>, pixel: vec2<f32>) -> vec3<f32> {
+    let steps = f32(max(post.levels, 2u) - 1u);
+    let offset = (threshold(pixel) - 0.5) * post.dither_strength / steps;
+    let rgb = saturate(color + offset);
+
+    if post.palette_size > 0u {
+        return nearest_in_palette(rgb);
+    }
+    return round(rgb * steps) / steps;
+}
+
+// Bend the picture like a CRT tube. `centered` goes from -1 to 1.
+fn curve(centered: vec2<f32>) -> vec2<f32> {
+    return centered * (1.0 + post.curvature * centered.yx * centered.yx);
+}
+
+fn pass_at(i: u32) -> u32 {
+    return post.order[i / 4u][i % 4u];
+}
+
+// Run a pass that only changes colors on the picture at `centered`.
+fn recolor(pass_id: u32, color: vec3<f32>, centered: vec2<f32>, size: vec2<f32>) -> vec3<f32> {
+    // Work in game pixels, not window pixels.
+    let pixel = (centered * 0.5 + 0.5) * size;
+    switch pass_id {
+        case PASS_QUANTIZE: {
+            return quantize(color, pixel);
+        }
+        case PASS_CRT: {
+            let line = 0.5 - 0.5 * cos(fract(pixel.y) * TAU);
+            return color * (1.0 - post.scanlines * (1.0 - line));
+        }
+        case PASS_VIGNETTE: {
+            let edge = smoothstep(post.vignette_radius, post.vignette_radius + 0.6, length(centered));
+            return color * (1.0 - post.vignette_strength * edge);
+        }
+        case PASS_FADE: {
+            return mix(color, post.fade_color.rgb, post.fade_amount);
+        }
+        default: {
+            return color;
+        }
+    }
+}
+
+@fragment
+fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(material_color_texture));
+
+    var count = 0u;
+    while count < 8u && pass_at(count) != 0u {
+        count++;
+    }
+
+    // Curvature and aberration move where the picture is read, so walk the
+    // chain backwards to find where each pass reads it. Red, green and blue
+    // are followed apart, as aberration splits them.
+    var at: array<array<vec2<f32>, 8>, 3>;
+    var reads = array<vec2<f32>, 3>();
+    for (var c = 0u; c < 3u; c++) {
+        reads[c] = mesh.uv * 2.0 - 1.0;
+    }
+    for (var i = count; i > 0u; i--) {
+        let pass_id = pass_at(i - 1u);
+        for (var c = 0u; c < 3u; c++) {
+            if pass_id == PASS_CRT {
+                // Scanlines follow the bend.
+                reads[c] = curve(reads[c]);
+            } else if pass_id == PASS_ABERRATION {
+                // Red outwards, green stays and blue inwards.
+                reads[c] += (1.0 - f32(c)) * reads[c] * post.aberration * 2.0 / size;
+            }
+            at[c][i - 1u] = reads[c];
+        }
+    }
+
+    // Sampling stays outside of branches for WebGPU.
+    let red = textureSample(material_color_texture, material_color_sampler, reads[0] * 0.5 + 0.5);
+    let green = textureSample(material_color_texture, material_color_sampler, reads[1] * 0.5 + 0.5);
+    let blue = textureSample(material_color_texture, material_color_sampler, reads[2] * 0.5 + 0.5);
+    var colors = array<vec3<f32>, 3>(red.rgb, green.rgb, blue.rgb);
+
+    for (var i = 0u; i < count; i++) {
+        let pass_id = pass_at(i);
+        if pass_id == PASS_ABERRATION {
+            let split = vec3<f32>(colors[0].r, colors[1].g, colors[2].b);
+            for (var c = 0u; c < 3u; c++) {
+                colors[c] = split;
+            }
+            continue;
+        }
+        for (var c = 0u; c < 3u; c++) {
+            colors[c] = recolor(pass_id, colors[c], at[c][i], size);
+        }
+    }
+
+    if any(abs(reads[1]) > vec2<f32>(1.0)) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    // The three agree once aberration has merged them, or if it never ran.
+    return vec4<f32>(colors[1], green.a);
+}
@@ -0,0 +1,9 @@
+#import bevy_pbr::forward_io::{Vertex, VertexOutput}
+#import "billboard_facing.wgsl"::billboard_vertex
+
+// Shaded mode draws with the standard fragment shader. Only the vertex shader
+// is replaced, so billboards face every view like they do in flat mode.
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    return billboard_vertex(vertex);
+}
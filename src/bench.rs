@@ -0,0 +1,56 @@
+use crate::billboard::{Billboard, BillboardMode, DrawDistance};
+use crate::instancing::InstancedBillboard;
+use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
+use bevy::image::{ImageLoaderSettings, ImageSampler};
+use bevy::prelude::*;
+
+/// Pass this on the command line to fill the level with instanced billboards.
+const BENCH_FLAG: &str = "--bench-billboards";
+
+/// Boards per side of the benchmark grid.
+const BENCH_SIDE: i32 = 128;
+
+/// World units between neighbouring boards.
+const BENCH_SPACING: f32 = 0.5;
+
+/// Reaches across the whole grid from anywhere on it, so every board is drawn.
+const BENCH_DRAW_DISTANCE: f32 = BENCH_SIDE as f32 * BENCH_SPACING * 2.0;
+
+pub fn plugin(app: &mut App) {
+    if !std::env::args().any(|a| a == BENCH_FLAG) {
+        return;
+    }
+
+    app.add_plugins((
+        FrameTimeDiagnosticsPlugin::default(),
+        LogDiagnosticsPlugin::default(),
+    ));
+    app.add_systems(Startup, setup);
+}
+
+fn setup(mut commands: Commands, assets: Res<AssetServer>) {
+    let texture: Handle<Image> = assets.load_with_settings("bill_smile.png", |s: &mut _| {
+        *s = ImageLoaderSettings {
+            sampler: ImageSampler::nearest(),
+            ..default()
+        }
+    });
+
+    let half = BENCH_SIDE / 2;
+    for i in -half..half {
+        for j in -half..half {
+            commands.spawn((
+                InstancedBillboard::new(texture.clone()),
+                Billboard::with_mode(BillboardMode::Cylindrical),
+                DrawDistance::new(BENCH_DRAW_DISTANCE, 4.0),
+                Transform::from_xyz(i as f32 * BENCH_SPACING, 0.25, j as f32 * BENCH_SPACING)
+                    .with_scale(Vec3::splat(BENCH_SPACING)),
+            ));
+        }
+    }
+
+    info!(
+        "Billboard benchmark: {} instanced boards",
+        BENCH_SIDE * BENCH_SIDE
+    );
+}
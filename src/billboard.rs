@@ -1,96 +1,505 @@
-use crate::{MainCamera, player};
+use crate::flat::{FlatMaterial, MAX_SORT_BIAS, MaterialCache, ShadedMaterial, own_material};
+use crate::instancing::InstancedBillboard;
+use crate::{GameSize, MainCamera, interact};
 use bevy::prelude::*;
-use leafwing_input_manager::prelude::ActionState;
-use parry3d::math::{Isometry, Point};
-use parry3d::na::{Point3, Vector3};
-use parry3d::query::{Ray, RayCast};
+use bevy::render::mesh::{MeshAabb, MeshTag};
+use bevy::render::primitives::Aabb;
+use bevy::render::view::{VisibilityRange, VisibilitySystems};
 use std::f32::consts::TAU;
 
+/// How a billboard orients itself towards the camera.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BillboardMode {
+    /// Align to the camera's view plane.
+    #[default]
+    ViewPlane,
+    /// Only rotate around the Y axis, so the board never tilts.
+    Cylindrical,
+    /// Turn towards the camera's position instead of its view direction.
+    PointAt,
+}
+
+impl BillboardMode {
+    /// Rotation turning a board at `position` towards `camera`. The quad faces
+    /// +Z, so it ends up looking *away* from the camera. `None` when the
+    /// camera sits on the board.
+    pub fn facing(self, position: Vec3, camera: &Transform) -> Option<Quat> {
+        match self {
+            BillboardMode::ViewPlane => {
+                let forward = camera.forward();
+                Some(Transform::IDENTITY.looking_to(forward, Vec3::Y).rotation)
+            }
+            BillboardMode::Cylindrical => {
+                let cam_yaw = camera.rotation.to_euler(EulerRot::YXZ).0;
+                Some(Quat::from_rotation_y(cam_yaw))
+            }
+            BillboardMode::PointAt => {
+                let away = position - camera.translation;
+                (away.length_squared() > f32::EPSILON)
+                    .then(|| Transform::IDENTITY.looking_to(away, Vec3::Y).rotation)
+            }
+        }
+    }
+}
+
+/// Sort bias between two neighbouring [`BlendSort::Layer`]s, in world units.
+const LAYER_DEPTH: f32 = 0.01;
+
+/// Draw order of a board using `AlphaMode::Blend`.
+///
+/// The order is written into the board's material. A board on a cached material
+/// gets a copy of its own first, but boards sharing any other material also
+/// share a sort policy. Biases are kept within
+/// [`MAX_SORT_BIAS`], so they settle overlaps between boards at about the same
+/// distance rather than reorder the whole scene.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+pub enum BlendSort {
+    /// Back to front by camera distance.
+    #[default]
+    Distance,
+    /// Drawn over boards in a lower layer close to it, then by distance.
+    Layer(i16),
+    /// Pretend the board is this many world units closer to the camera.
+    Bias(f32),
+}
+
+impl BlendSort {
+    pub fn depth_bias(&self) -> f32 {
+        let bias = match *self {
+            BlendSort::Distance => 0.0,
+            BlendSort::Layer(layer) => layer as f32 * LAYER_DEPTH,
+            BlendSort::Bias(bias) => bias,
+        };
+        bias.clamp(-MAX_SORT_BIAS, MAX_SORT_BIAS)
+    }
+}
+
+/// Keep a billboard the same size on screen, whatever its distance.
+///
+/// The board's own scale is kept and multiplied by the screen scale, so
+/// stretched boards stay stretched.
+#[derive(Clone, Copy, Debug)]
+pub struct ScreenSize {
+    /// `RenderTex` pixels covered by one world unit of the board's mesh.
+    pub pixels: f32,
+    /// Smallest screen scale the board may shrink to.
+    pub min: Option<f32>,
+    /// Largest screen scale the board may grow to.
+    pub max: Option<f32>,
+}
+
+impl ScreenSize {
+    pub fn new(pixels: f32) -> Self {
+        ScreenSize {
+            pixels,
+            min: None,
+            max: None,
+        }
+    }
+}
+
+/// A quad turning to face the camera.
+///
+/// Boards aren't pickable on their own, since building the shape adds up over
+/// thousands of instanced boards or particles. Add an
+/// `Interactable::quad(Vec2::ONE)` (sized like the mesh) to click them.
 #[derive(Component)]
-#[require(Mesh3d)]
+#[require(Mesh3d, MeshTag, DrawDistance)]
 pub struct Billboard {
+    pub mode: BillboardMode,
+    /// Spin around the board's facing axis, in radians.
     spin: f32,
+    /// The spin currently turned into the transform's rotation.
+    applied_spin: f32,
+    /// Radians per second.
+    pub angular_velocity: f32,
+    /// Exponential slow-down per second. 0 spins forever.
+    pub damping: f32,
+    /// Upper bound on `angular_velocity`, in radians per second.
+    pub max_speed: f32,
+    /// Angular velocity added when the board is clicked.
+    pub flick: f32,
+    /// When set, the board's scale is driven by the camera every frame.
+    pub screen_size: Option<ScreenSize>,
+    /// The screen scale currently multiplied into the transform.
+    screen_scale: f32,
 }
 
 impl Default for Billboard {
     fn default() -> Self {
-        Billboard { spin: 0.0 }
+        Billboard {
+            mode: BillboardMode::default(),
+            spin: 0.0,
+            applied_spin: 0.0,
+            angular_velocity: 0.0,
+            damping: 1.5,
+            max_speed: TAU * 4.0,
+            flick: TAU * 2.0,
+            screen_size: None,
+            screen_scale: 1.0,
+        }
     }
 }
 
-fn face_billboards(
-    mut boards: Query<&mut Transform, (With<Billboard>, Without<MainCamera>)>,
+impl Billboard {
+    pub fn with_mode(mode: BillboardMode) -> Self {
+        Billboard { mode, ..default() }
+    }
+
+    pub fn with_screen_size(mut self, screen_size: ScreenSize) -> Self {
+        self.screen_size = Some(screen_size);
+        self
+    }
+
+    /// Add `amount` radians per second of spin, clamped to `max_speed`.
+    pub fn impulse(&mut self, amount: f32) {
+        self.angular_velocity =
+            (self.angular_velocity + amount).clamp(-self.max_speed, self.max_speed);
+    }
+
+    /// Slow down, then turn by the angular velocity over `dt` seconds.
+    fn advance(&mut self, dt: f32) {
+        self.angular_velocity *= (-self.damping * dt).exp();
+        self.angular_velocity = self.angular_velocity.clamp(-self.max_speed, self.max_speed);
+        self.spin = (self.spin + self.angular_velocity * dt).rem_euclid(TAU);
+    }
+}
+
+/// Stop drawing and updating a board past `max` world units from the camera,
+/// dithering it out over the last `fade` units.
+///
+/// Every billboard gets the default; insert one to override it per board.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct DrawDistance {
+    pub max: f32,
+    pub fade: f32,
+}
+
+impl Default for DrawDistance {
+    fn default() -> Self {
+        DrawDistance {
+            max: 64.0,
+            fade: 8.0,
+        }
+    }
+}
+
+impl DrawDistance {
+    pub fn new(max: f32, fade: f32) -> Self {
+        DrawDistance { max, fade }
+    }
+}
+
+/// Marks a board that is too far away to bother facing or spinning.
+#[derive(Component)]
+#[component(storage = "SparseSet")]
+struct Culled;
+
+fn sync_draw_distance(
+    mut commands: Commands,
+    boards: Query<(Entity, &DrawDistance), Changed<DrawDistance>>,
+) {
+    for (e, dist) in &boards {
+        // Bevy culls past the range and dithers the fade in the material shaders.
+        commands.entity(e).insert(VisibilityRange {
+            start_margin: 0.0..0.0,
+            end_margin: (dist.max - dist.fade).max(0.0)..dist.max,
+            use_aabb: false,
+        });
+    }
+}
+
+/// Measured from the first `MainCamera` only, so a board culled here still
+/// draws in other views but stops facing and spinning on the CPU.
+fn cull_boards(
+    mut commands: Commands,
+    boards: Query<(Entity, &Transform, &DrawDistance, Has<Culled>), With<Billboard>>,
     cam: Query<&Transform, With<MainCamera>>,
 ) {
-    let cam_transform = cam.single().unwrap();
-    for mut transform in &mut boards {
-        transform.rotation = Quat::IDENTITY;
-        let target = transform.translation + Vec3::from(cam_transform.forward());
-        transform.look_at(target, Vec3::Y);
+    let Some(cam_transform) = cam.iter().next() else {
+        return;
+    };
+    for (e, transform, dist, culled) in &boards {
+        let far = transform
+            .translation
+            .distance_squared(cam_transform.translation)
+            > dist.max * dist.max;
+        if far && !culled {
+            commands.entity(e).insert(Culled);
+        } else if !far && culled {
+            commands.entity(e).remove::<Culled>();
+        }
     }
 }
 
-fn billboard_interaction(
-    boards: Query<(&mut Billboard, &Transform)>,
-    players: Query<
-        (&Transform, &ActionState<player::PlayerAction>),
-        (With<player::Player>, Without<Billboard>),
+/// Pack a board's facing mode and spin for `billboard_facing.wgsl`, which every
+/// billboard material uses to turn the quad towards the camera drawing it.
+pub fn facing_tag(mode: BillboardMode, spin: f32) -> MeshTag {
+    let mode = match mode {
+        BillboardMode::ViewPlane => 1,
+        BillboardMode::Cylindrical => 2,
+        BillboardMode::PointAt => 3,
+    };
+    let spin = ((spin.rem_euclid(TAU) / TAU) * 65536.0) as u32 & 0xffff;
+    MeshTag(spin << 16 | mode)
+}
+
+/// Face the first `MainCamera` on the CPU. Both materials re-face every view in
+/// the vertex shader, so this is only what picking sees.
+fn face_billboards(
+    mut boards: Query<
+        (&mut Transform, &mut Billboard),
+        (
+            Without<MainCamera>,
+            Without<InstancedBillboard>,
+            Without<Culled>,
+        ),
     >,
-    time: Res<Time>,
+    cam: Query<&Transform, With<MainCamera>>,
 ) {
-    let (p_trans, action) = players.single().unwrap();
-
-    if !action.pressed(&player::PlayerAction::Click) {
+    let Some(cam_transform) = cam.iter().next() else {
         return;
+    };
+    for (mut transform, mut bill) in &mut boards {
+        if let Some(rotation) = bill.mode.facing(transform.translation, cam_transform) {
+            transform.rotation = rotation;
+            bill.applied_spin = 0.0;
+        }
     }
+}
 
-    let dir = p_trans.forward();
-    let ray = Ray::new(
-        Point::new(
-            p_trans.translation.x,
-            p_trans.translation.y,
-            p_trans.translation.z,
-        ),
-        Vector3::new(dir.x, dir.y, dir.z),
-    );
+/// Sized for the first `MainCamera`. Other views see the same world size, so
+/// the board only keeps its pixel size in that one.
+fn size_billboards(
+    mut boards: Query<(&mut Transform, &mut Billboard), (Without<MainCamera>, Without<Culled>)>,
+    cam: Query<(&Transform, &Projection), With<MainCamera>>,
+    game_size: Res<GameSize>,
+) {
+    let Some((cam_transform, projection)) = cam.iter().next() else {
+        return;
+    };
+    let height = game_size.0.height as f32;
 
-    let points = vec![
-        Point3::new(0.5, 0.5, 0.0),
-        Point3::new(0.5, -0.5, 0.0),
-        Point3::new(-0.5, -0.5, 0.0),
-        Point3::new(-0.5, 0.5, 0.0),
-    ];
+    for (mut transform, mut bill) in &mut boards {
+        let Some(screen) = bill.screen_size else {
+            // Hand back the authored scale once the screen size is cleared.
+            if bill.screen_scale != 1.0 {
+                transform.scale /= bill.screen_scale;
+                bill.screen_scale = 1.0;
+            }
+            continue;
+        };
 
-    let indices = vec![[0u32, 1, 3], [2, 3, 1]];
+        // World units covered by one pixel at the board's depth.
+        let pixel = match projection {
+            Projection::Perspective(p) => {
+                let depth = (transform.translation - cam_transform.translation)
+                    .dot(Vec3::from(cam_transform.forward()));
+                2.0 * depth.max(0.0) * (p.fov * 0.5).tan() / height
+            }
+            Projection::Orthographic(o) => o.area.height() / height,
+            _ => continue,
+        };
 
-    let quad = parry3d::shape::TriMesh::new(points, indices).unwrap();
+        let mut size = screen.pixels * pixel;
+        if let Some(min) = screen.min {
+            size = size.max(min);
+        }
+        if let Some(max) = screen.max {
+            size = size.min(max);
+        }
+        // Never all the way to 0, so the authored scale can be recovered.
+        let size = size.max(1e-4);
+        transform.scale *= size / bill.screen_scale;
+        bill.screen_scale = size;
+    }
+}
 
-    for (mut bill, b_trans) in boards {
-        let b_mesh = quad.clone();
+/// Bounds covering every way a board can face. The shaders turn it per view,
+/// so the mesh's own bounds, which only fit the CPU facing, would cull it
+/// wrongly in the other views.
+fn bound_billboards(
+    mut commands: Commands,
+    meshes: Res<Assets<Mesh>>,
+    boards: Query<(Entity, &Mesh3d), (With<Billboard>, Or<(Changed<Mesh3d>, Without<Aabb>)>)>,
+) {
+    for (e, mesh) in &boards {
+        let Some(aabb) = meshes.get(mesh).and_then(|m| m.compute_aabb()) else {
+            continue;
+        };
+        let reach = Vec3::splat(aabb.center.length() + aabb.half_extents.length());
+        commands
+            .entity(e)
+            .try_insert(Aabb::from_min_max(-reach, reach));
+    }
+}
 
-        let res = b_mesh.cast_ray(
-            &Isometry::new(
-                Vector3::from(b_trans.translation.to_array()),
-                Vector3::from(b_trans.rotation.to_scaled_axis().to_array()),
-            ),
-            &ray,
-            50.0,
-            true,
-        );
+fn apply_blend_sort(
+    mut commands: Commands,
+    mut f_mats: ResMut<Assets<FlatMaterial>>,
+    mut s_mats: ResMut<Assets<ShadedMaterial>>,
+    cache: Res<MaterialCache>,
+    boards: Query<
+        (
+            Entity,
+            &BlendSort,
+            Option<&MeshMaterial3d<FlatMaterial>>,
+            Option<&MeshMaterial3d<ShadedMaterial>>,
+        ),
+        Or<(
+            Changed<BlendSort>,
+            Changed<MeshMaterial3d<FlatMaterial>>,
+            Changed<MeshMaterial3d<ShadedMaterial>>,
+        )>,
+    >,
+) {
+    for (e, sort, f_mat, s_mat) in boards {
+        let bias = sort.depth_bias();
+        if let Some(m) = f_mat
+            && f_mats.get(m).is_some_and(|mat| mat.depth_bias != bias)
+            && let Some(mat) = own_material(&mut commands, &cache, e, m, &mut f_mats)
+        {
+            mat.depth_bias = bias;
+        }
+        if let Some(m) = s_mat
+            && s_mats.get(m).is_some_and(|mat| mat.base.depth_bias != bias)
+            && let Some(mat) = own_material(&mut commands, &cache, e, m, &mut s_mats)
+        {
+            mat.base.depth_bias = bias;
+        }
+    }
+}
 
-        if let Some(_) = res {
-            bill.spin += TAU * 2.0 * time.delta_secs();
+/// Flick a board when it's clicked, and keep winding it up while held.
+fn billboard_interaction(
+    mut boards: Query<&mut Billboard>,
+    mut clicks: EventReader<interact::Clicked>,
+    mut holds: EventReader<interact::Held>,
+    time: Res<Time>,
+) {
+    for interact::Clicked(hit) in clicks.read() {
+        if let Ok(mut bill) = boards.get_mut(hit.entity) {
+            let flick = bill.flick;
+            bill.impulse(flick);
+        }
+    }
+    for interact::Held(hit) in holds.read() {
+        if let Ok(mut bill) = boards.get_mut(hit.entity) {
+            let flick = bill.flick;
+            bill.impulse(flick * time.delta_secs());
         }
     }
 }
 
-fn rot_boards(boards: Query<(&mut Transform, &Billboard)>) {
-    for (mut trans, bill) in boards {
-        trans.rotate_local_z(bill.spin);
+fn rot_boards(
+    boards: Query<
+        (
+            &mut Transform,
+            &mut Billboard,
+            &mut MeshTag,
+            Has<InstancedBillboard>,
+        ),
+        Without<Culled>,
+    >,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    for (mut trans, mut bill, mut tag, instanced) in boards {
+        bill.advance(dt);
+
+        // Only turn by what the rotation is missing, since facing doesn't
+        // reset it when there is no camera to face. Instanced boards are only
+        // turned by the shader.
+        if !instanced {
+            trans.rotate_local_z(bill.spin - bill.applied_spin);
+            bill.applied_spin = bill.spin;
+        }
+        tag.set_if_neq(facing_tag(bill.mode, bill.spin));
     }
 }
 
 pub fn plugin(app: &mut App) {
-    app.add_systems(PreUpdate, face_billboards);
-    app.add_systems(Update, (billboard_interaction, rot_boards).chain());
+    app.add_systems(
+        PreUpdate,
+        (
+            (sync_draw_distance, cull_boards),
+            face_billboards,
+            size_billboards,
+        )
+            .chain(),
+    );
+    app.add_systems(
+        Update,
+        (billboard_interaction, rot_boards)
+            .chain()
+            .after(interact::pick),
+    );
+    app.add_systems(
+        PostUpdate,
+        (
+            bound_billboards.before(VisibilitySystems::CalculateBounds),
+            apply_blend_sort,
+        ),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Where the quad's visible side points, which should be at the camera.
+    fn front(rotation: Quat) -> Vec3 {
+        rotation * Vec3::Z
+    }
+
+    #[test]
+    fn view_plane_matches_the_camera() {
+        let cam = Transform::from_xyz(3.0, 2.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y);
+        let rot = BillboardMode::ViewPlane
+            .facing(Vec3::new(-5.0, 0.0, 1.0), &cam)
+            .unwrap();
+        assert!(front(rot).abs_diff_eq(Vec3::from(cam.back()), 1e-5));
+    }
+
+    #[test]
+    fn cylindrical_stays_upright() {
+        let cam = Transform::from_xyz(0.0, 5.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y);
+        let rot = BillboardMode::Cylindrical.facing(Vec3::ZERO, &cam).unwrap();
+        assert!((rot * Vec3::Y).abs_diff_eq(Vec3::Y, 1e-5));
+        assert!(front(rot).abs_diff_eq(Vec3::Z, 1e-5));
+    }
+
+    #[test]
+    fn impulse_stops_at_max_speed() {
+        let mut bill = Billboard::default();
+        bill.impulse(bill.max_speed * 3.0);
+        assert_eq!(bill.angular_velocity, bill.max_speed);
+        bill.impulse(-bill.max_speed * 5.0);
+        assert_eq!(bill.angular_velocity, -bill.max_speed);
+    }
+
+    #[test]
+    fn damping_slows_the_spin() {
+        let mut bill = Billboard {
+            damping: 2.0,
+            angular_velocity: 4.0,
+            ..default()
+        };
+        // Half the speed is gone after ln(2) / damping seconds.
+        bill.advance(std::f32::consts::LN_2 / 2.0);
+        assert!((bill.angular_velocity - 2.0).abs() < 1e-5);
+
+        bill.damping = 0.0;
+        bill.advance(1.0);
+        assert!((bill.angular_velocity - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn point_at_faces_the_camera_position() {
+        // Looking away from the board, which the view plane mode wouldn't notice.
+        let cam = Transform::from_xyz(0.0, 0.0, 4.0).looking_to(Vec3::X, Vec3::Y);
+        let rot = BillboardMode::PointAt.facing(Vec3::ZERO, &cam).unwrap();
+        assert!(front(rot).abs_diff_eq(Vec3::Z, 1e-5));
+        assert_eq!(BillboardMode::PointAt.facing(cam.translation, &cam), None);
+    }
 }
@@ -0,0 +1,577 @@
+use crate::cube::{Cube, CubeAtlas, CubeGrid, CubeTiles};
+use crate::flat::{FlatMaterial, ShadedMaterial};
+use bevy::asset::RenderAssetUsages;
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+
+/// Cells along each side of a chunk.
+pub const CHUNK_SIZE: i32 = 16;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<CubeWorld>();
+    app.add_observer(unindex_cube);
+    app.add_systems(
+        PostUpdate,
+        (index_cubes, mark_changed, rebuild_chunks).chain(),
+    );
+}
+
+/// Grid-aligned cubes sorted into chunks. Each chunk is drawn as one merged
+/// mesh per material, with the faces between cubes left out.
+///
+/// The `Cube` entities stay around (hidden) for picking and editing, and this
+/// doubles as a spatial index for collision. Moving an indexed cube takes it
+/// out of its cell and indexes it again where it lands.
+#[derive(Resource, Default)]
+pub struct CubeWorld {
+    chunks: HashMap<IVec3, Chunk>,
+    /// Cubes that don't fit the grid and draw themselves.
+    loose: HashSet<Entity>,
+}
+
+#[derive(Default)]
+struct Chunk {
+    cells: HashMap<IVec3, Entity>,
+    sections: Vec<Entity>,
+    dirty: bool,
+}
+
+/// The grid cell a cube has been indexed under.
+#[derive(Component)]
+pub struct CubeCell(pub IVec3);
+
+fn chunk_of(cell: IVec3) -> IVec3 {
+    cell.div_euclid(IVec3::splat(CHUNK_SIZE))
+}
+
+impl CubeWorld {
+    pub fn get(&self, cell: IVec3) -> Option<Entity> {
+        self.chunks.get(&chunk_of(cell))?.cells.get(&cell).copied()
+    }
+
+    /// Cubes that don't fit the grid.
+    pub fn loose(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.loose.iter().copied()
+    }
+
+    /// Cubes whose column on the XZ plane may touch a circle of `radius` around
+    /// `point`, at any height, plus every loose cube.
+    pub fn near(
+        &self,
+        grid: &CubeGrid,
+        point: Vec2,
+        radius: f32,
+    ) -> impl Iterator<Item = Entity> + '_ {
+        let min = grid.cell(Vec3::new(point.x - radius, 0.0, point.y - radius));
+        let max = grid.cell(Vec3::new(point.x + radius, 0.0, point.y + radius));
+        let (c_min, c_max) = (chunk_of(min), chunk_of(max));
+
+        self.chunks
+            .iter()
+            .filter(move |(c, _)| {
+                (c_min.x..=c_max.x).contains(&c.x) && (c_min.z..=c_max.z).contains(&c.z)
+            })
+            .flat_map(|(_, chunk)| chunk.cells.iter())
+            .filter(move |(cell, _)| {
+                (min.x..=max.x).contains(&cell.x) && (min.z..=max.z).contains(&cell.z)
+            })
+            .map(|(_, e)| *e)
+            .chain(self.loose.iter().copied())
+    }
+
+    fn insert(&mut self, cell: IVec3, e: Entity) {
+        self.chunks
+            .entry(chunk_of(cell))
+            .or_default()
+            .cells
+            .insert(cell, e);
+        self.touch(cell);
+    }
+
+    /// Take `e` out of `cell`, if it is still the cube there.
+    fn remove(&mut self, cell: IVec3, e: Entity) {
+        if let Some(chunk) = self.chunks.get_mut(&chunk_of(cell))
+            && chunk.cells.get(&cell) == Some(&e)
+        {
+            chunk.cells.remove(&cell);
+        }
+        self.touch(cell);
+    }
+
+    /// Flag the chunk holding `cell` and any chunk sharing a face with it.
+    fn touch(&mut self, cell: IVec3) {
+        let chunks: HashSet<IVec3> = [
+            IVec3::ZERO,
+            IVec3::X,
+            IVec3::NEG_X,
+            IVec3::Y,
+            IVec3::NEG_Y,
+            IVec3::Z,
+            IVec3::NEG_Z,
+        ]
+        .into_iter()
+        .map(|d| chunk_of(cell + d))
+        .collect();
+
+        for c in chunks {
+            if let Some(chunk) = self.chunks.get_mut(&c) {
+                chunk.dirty = true;
+            }
+        }
+    }
+}
+
+fn index_cubes(
+    mut commands: Commands,
+    mut world: ResMut<CubeWorld>,
+    grid: Res<CubeGrid>,
+    cubes: Query<(Entity, &Transform, Option<&CubeCell>), (With<Cube>, Changed<Transform>)>,
+) {
+    for (e, trans, indexed) in &cubes {
+        let cell = grid.cell(trans.translation);
+        let aligned = trans.rotation.is_near_identity()
+            && trans.scale.abs_diff_eq(Vec3::splat(grid.size), 1e-4)
+            && trans
+                .translation
+                .abs_diff_eq(grid.cell_center(trans.translation), 1e-4);
+
+        match indexed {
+            Some(CubeCell(old)) if aligned && *old == cell => continue,
+            Some(CubeCell(old)) => {
+                world.remove(*old, e);
+                commands
+                    .entity(e)
+                    .remove::<CubeCell>()
+                    .insert(Visibility::Inherited);
+            }
+            None => {
+                world.loose.remove(&e);
+            }
+        }
+
+        if !aligned || world.get(cell).is_some() {
+            world.loose.insert(e);
+            continue;
+        }
+
+        world.insert(cell, e);
+        commands
+            .entity(e)
+            .insert((CubeCell(cell), Visibility::Hidden));
+    }
+}
+
+fn unindex_cube(
+    trigger: Trigger<OnRemove, Cube>,
+    mut world: ResMut<CubeWorld>,
+    cells: Query<&CubeCell>,
+) {
+    let e = trigger.target();
+    world.loose.remove(&e);
+
+    if let Ok(CubeCell(cell)) = cells.get(e) {
+        world.remove(*cell, e);
+    }
+}
+
+fn mark_changed(
+    mut world: ResMut<CubeWorld>,
+    atlas: Res<CubeAtlas>,
+    cubes: Query<
+        &CubeCell,
+        Or<(
+            Changed<Cube>,
+            Changed<MeshMaterial3d<FlatMaterial>>,
+            Changed<MeshMaterial3d<ShadedMaterial>>,
+        )>,
+    >,
+) {
+    if atlas.is_changed() {
+        for chunk in world.chunks.values_mut() {
+            chunk.dirty = true;
+        }
+    }
+    for CubeCell(cell) in &cubes {
+        if let Some(chunk) = world.chunks.get_mut(&chunk_of(*cell)) {
+            chunk.dirty = true;
+        }
+    }
+}
+
+/// What a cube looks like. Cubes that look the same share a merged mesh.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Look {
+    flat: bool,
+    color: [u32; 4],
+    texture: Option<AssetId<Image>>,
+}
+
+#[derive(Clone)]
+enum SectionMaterial {
+    Flat(Handle<FlatMaterial>),
+    Standard(Handle<ShadedMaterial>),
+}
+
+fn rebuild_chunks(
+    mut commands: Commands,
+    mut world: ResMut<CubeWorld>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    f_mats: Res<Assets<FlatMaterial>>,
+    s_mats: Res<Assets<ShadedMaterial>>,
+    grid: Res<CubeGrid>,
+    atlas: Res<CubeAtlas>,
+    cubes: Query<(
+        &Cube,
+        Option<&MeshMaterial3d<FlatMaterial>>,
+        Option<&MeshMaterial3d<ShadedMaterial>>,
+    )>,
+) {
+    let dirty: Vec<IVec3> = world
+        .chunks
+        .iter()
+        .filter(|(_, chunk)| chunk.dirty)
+        .map(|(c, _)| *c)
+        .collect();
+
+    for c in dirty {
+        let chunk = world.chunks.get_mut(&c).unwrap();
+        chunk.dirty = false;
+        for section in chunk.sections.drain(..) {
+            commands.entity(section).despawn();
+        }
+
+        // Sort the cells by look, keeping the first material seen for each.
+        let mut looks: Vec<(Look, SectionMaterial)> = Vec::new();
+        let mut cells: HashMap<IVec3, (usize, Option<CubeTiles>)> = HashMap::default();
+        for (cell, e) in &chunk.cells {
+            let Ok((cube, f_mat, s_mat)) = cubes.get(*e) else {
+                continue;
+            };
+            let (look, mat) = if let Some(m) = f_mat
+                && let Some(mat) = f_mats.get(m.id())
+            {
+                let look = Look {
+                    flat: true,
+                    color: mat.color.to_f32_array().map(f32::to_bits),
+                    texture: mat.texture.as_ref().map(|t| t.id()),
+                };
+                (look, SectionMaterial::Flat(m.0.clone()))
+            } else if let Some(m) = s_mat
+                && let Some(mat) = s_mats.get(m.id())
+            {
+                let look = Look {
+                    flat: false,
+                    color: mat
+                        .base
+                        .base_color
+                        .to_linear()
+                        .to_f32_array()
+                        .map(f32::to_bits),
+                    texture: mat.base.base_color_texture.as_ref().map(|t| t.id()),
+                };
+                (look, SectionMaterial::Standard(m.0.clone()))
+            } else {
+                continue;
+            };
+
+            let i = match looks.iter().position(|(l, _)| *l == look) {
+                Some(i) => i,
+                None => {
+                    looks.push((look, mat));
+                    looks.len() - 1
+                }
+            };
+            cells.insert(*cell, (i, cube.tiles));
+        }
+
+        let origin = c * CHUNK_SIZE;
+        let occupied = |cell: IVec3| world.get(cell).is_some();
+
+        let mut sections = Vec::new();
+        for (i, (_, mat)) in looks.iter().enumerate() {
+            let Some(mesh) = greedy_mesh(origin, i, &cells, occupied, &atlas) else {
+                continue;
+            };
+            let mut section = commands.spawn((
+                Mesh3d(meshes.add(mesh)),
+                Transform::from_translation(origin.as_vec3() * grid.size)
+                    .with_scale(Vec3::splat(grid.size)),
+            ));
+            match mat {
+                SectionMaterial::Flat(h) => section.insert(MeshMaterial3d(h.clone())),
+                SectionMaterial::Standard(h) => section.insert(MeshMaterial3d(h.clone())),
+            };
+            sections.push(section.id());
+        }
+
+        let chunk = world.chunks.get_mut(&c).unwrap();
+        chunk.sections = sections;
+        if chunk.cells.is_empty() {
+            world.chunks.remove(&c);
+        }
+    }
+}
+
+/// Mesh the exposed faces of the cells with look `look`. Plain faces next to
+/// each other are merged into rectangles, atlas tiles are kept one per cell.
+/// Positions are in cells, relative to `origin`.
+fn greedy_mesh(
+    origin: IVec3,
+    look: usize,
+    cells: &HashMap<IVec3, (usize, Option<CubeTiles>)>,
+    occupied: impl Fn(IVec3) -> bool,
+    atlas: &CubeAtlas,
+) -> Option<Mesh> {
+    let n = CHUNK_SIZE as usize;
+    let mut faces = Faces::default();
+    let mut mask = vec![None; n * n];
+
+    for d in 0..3 {
+        let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+        for sign in [1, -1] {
+            let face = d * 2 + (sign < 0) as usize;
+            let mut step = IVec3::ZERO;
+            step[d] = sign;
+
+            for slice in 0..CHUNK_SIZE {
+                let local = |i: usize, j: usize| {
+                    let mut local = IVec3::ZERO;
+                    local[d] = slice;
+                    local[u] = i as i32;
+                    local[v] = j as i32;
+                    local
+                };
+
+                for j in 0..n {
+                    for i in 0..n {
+                        let cell = origin + local(i, j);
+                        mask[i + j * n] = cells
+                            .get(&cell)
+                            .filter(|(l, _)| *l == look && !occupied(cell + step))
+                            .map(|(_, tiles)| tiles.map(|t| t.0[face]));
+                    }
+                }
+
+                merge_mask(&mut mask, n, |i, j, w, h, tile| {
+                    faces.quad(
+                        d,
+                        sign,
+                        local(i, j).as_vec3(),
+                        Vec2::new(w as f32, h as f32),
+                        tile.map(|t| atlas.tile_rect(t)),
+                    );
+                });
+            }
+        }
+    }
+
+    faces.into_mesh()
+}
+
+/// Cover a slice's mask with rectangles, growing along a row then down the
+/// rows. Only plain faces (`Some(None)`) are merged.
+fn merge_mask(
+    mask: &mut [Option<Option<u16>>],
+    n: usize,
+    mut emit: impl FnMut(usize, usize, usize, usize, Option<u16>),
+) {
+    for j in 0..n {
+        let mut i = 0;
+        while i < n {
+            let Some(tile) = mask[i + j * n] else {
+                i += 1;
+                continue;
+            };
+
+            let (mut w, mut h) = (1, 1);
+            if tile.is_none() {
+                while i + w < n && mask[i + w + j * n] == Some(None) {
+                    w += 1;
+                }
+                while j + h < n && (i..i + w).all(|k| mask[k + (j + h) * n] == Some(None)) {
+                    h += 1;
+                }
+            }
+            for jj in j..j + h {
+                mask[i + jj * n..i + w + jj * n].fill(None);
+            }
+
+            emit(i, j, w, h, tile);
+            i += w;
+        }
+    }
+}
+
+/// A single cube with its faces taken from the atlas, centered on the origin.
+pub fn tiled_cube_mesh(tiles: CubeTiles, atlas: &CubeAtlas) -> Mesh {
+    let mut faces = Faces::default();
+    for d in 0..3 {
+        for sign in [1, -1] {
+            let face = d * 2 + (sign < 0) as usize;
+            let rect = atlas.tile_rect(tiles.0[face]);
+            faces.quad(d, sign, Vec3::splat(-0.5), Vec2::ONE, Some(rect));
+        }
+    }
+    faces.into_mesh().unwrap()
+}
+
+#[derive(Default)]
+struct Faces {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    indices: Vec<u32>,
+}
+
+/// Texture coordinates for a point on a face along axis `d`, upright on the
+/// sides.
+fn face_uv(d: usize, p: Vec3) -> Vec2 {
+    match d {
+        0 => Vec2::new(p.z, 1.0 - p.y),
+        1 => Vec2::new(p.x, p.z),
+        _ => Vec2::new(p.x, 1.0 - p.y),
+    }
+}
+
+impl Faces {
+    /// Add a `size` cells rectangle facing along axis `d` in direction `sign`,
+    /// on the cell at `min`. Without an atlas `tile` the texture repeats once
+    /// per cell.
+    fn quad(&mut self, d: usize, sign: i32, min: Vec3, size: Vec2, tile: Option<Rect>) {
+        let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+        let mut normal = Vec3::ZERO;
+        normal[d] = sign as f32;
+        let mut plane = min;
+        if sign > 0 {
+            plane[d] += 1.0;
+        }
+
+        let base = self.positions.len() as u32;
+        for (cu, cv) in [(0.0, 0.0), (size.x, 0.0), (size.x, size.y), (0.0, size.y)] {
+            let mut p = plane;
+            p[u] += cu;
+            p[v] += cv;
+            let uv = match tile {
+                Some(rect) => rect.min + face_uv(d, p - min) * rect.size(),
+                None => face_uv(d, p),
+            };
+            self.positions.push(p.to_array());
+            self.normals.push(normal.to_array());
+            self.uvs.push(uv.to_array());
+        }
+
+        // The corners wind counter-clockwise seen from the positive side.
+        if sign > 0 {
+            self.indices
+                .extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+        } else {
+            self.indices
+                .extend([base, base + 2, base + 1, base, base + 3, base + 2]);
+        }
+    }
+
+    fn into_mesh(self) -> Option<Mesh> {
+        if self.indices.is_empty() {
+            return None;
+        }
+
+        Some(
+            Mesh::new(
+                PrimitiveTopology::TriangleList,
+                RenderAssetUsages::default(),
+            )
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, self.positions)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs)
+            .with_inserted_indices(Indices::U32(self.indices)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atlas() -> CubeAtlas {
+        CubeAtlas {
+            texture: Handle::default(),
+            tile_size: UVec2::ONE,
+            columns: 2,
+            rows: 2,
+        }
+    }
+
+    /// Mesh chunk `c` of `world`, with every cube looking the same.
+    fn mesh_chunk(world: &CubeWorld, c: IVec3) -> Option<Mesh> {
+        let cells = world.chunks[&c]
+            .cells
+            .keys()
+            .map(|cell| (*cell, (0, None)))
+            .collect();
+        let occupied = |cell: IVec3| world.get(cell).is_some();
+        greedy_mesh(c * CHUNK_SIZE, 0, &cells, occupied, &atlas())
+    }
+
+    fn quads(mesh: &Mesh) -> usize {
+        mesh.count_vertices() / 4
+    }
+
+    #[test]
+    fn single_cube() {
+        let mut world = CubeWorld::default();
+        world.insert(IVec3::new(3, 0, 5), Entity::from_raw(1));
+        let mesh = mesh_chunk(&world, IVec3::ZERO).unwrap();
+        assert_eq!(quads(&mesh), 6);
+        assert_eq!(mesh.indices().unwrap().len(), 36);
+    }
+
+    #[test]
+    fn neighbours_merge() {
+        let mut world = CubeWorld::default();
+        world.insert(IVec3::new(0, 0, 0), Entity::from_raw(1));
+        world.insert(IVec3::new(1, 0, 0), Entity::from_raw(2));
+        // The shared faces go, and the four long sides become one quad each.
+        assert_eq!(quads(&mesh_chunk(&world, IVec3::ZERO).unwrap()), 6);
+    }
+
+    #[test]
+    fn face_hidden_across_chunks() {
+        let mut world = CubeWorld::default();
+        world.insert(IVec3::new(CHUNK_SIZE - 1, 0, 0), Entity::from_raw(1));
+        world.insert(IVec3::new(CHUNK_SIZE, 0, 0), Entity::from_raw(2));
+        assert_eq!(quads(&mesh_chunk(&world, IVec3::ZERO).unwrap()), 5);
+        assert_eq!(quads(&mesh_chunk(&world, IVec3::X).unwrap()), 5);
+    }
+
+    #[test]
+    fn tiles_stay_apart() {
+        let n = 4;
+        let mut mask = vec![Some(None); n * n];
+        mask[0] = Some(Some(2));
+        let mut rects = Vec::new();
+        merge_mask(&mut mask, n, |i, j, w, h, tile| {
+            rects.push((i, j, w, h, tile))
+        });
+        assert_eq!(
+            rects,
+            [
+                (0, 0, 1, 1, Some(2)),
+                (1, 0, 3, 4, None),
+                (0, 1, 1, 3, None),
+            ]
+        );
+        assert!(mask.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn remove_keeps_other_cube() {
+        let mut world = CubeWorld::default();
+        let cell = IVec3::new(-1, 2, -20);
+        world.insert(cell, Entity::from_raw(1));
+        world.remove(cell, Entity::from_raw(2));
+        assert_eq!(world.get(cell), Some(Entity::from_raw(1)));
+        world.remove(cell, Entity::from_raw(1));
+        assert_eq!(world.get(cell), None);
+    }
+}
@@ -1,16 +1,105 @@
-use crate::flat::{DynamicMaterial, FlatMaterial};
-use crate::{GameSettings, player};
-use bevy::image::ImageLoaderSettings;
+use crate::chunk::{CubeWorld, tiled_cube_mesh};
+use crate::flat::{DynamicMaterial, FlatMaterial, MaterialCache, ShadedMaterial};
+use crate::player::{Player, PlayerAction};
+use crate::rng::GameRng;
+use crate::{GameSettings, interact};
+use bevy::image::{ImageAddressMode, ImageLoaderSettings, ImageSampler, ImageSamplerDescriptor};
+use bevy::math::bounding::{Aabb3d, IntersectsVolume};
+use bevy::platform::collections::HashMap;
 use bevy::prelude::*;
 use leafwing_input_manager::prelude::ActionState;
-use parry3d::math::{Isometry, Point};
-use parry3d::na::Vector3;
-use parry3d::query::{Ray, RayCast};
-use rand::random;
+use rand::Rng;
 
-#[derive(Component)]
-#[require(Mesh3d, DynamicMaterial)]
-pub struct Cube;
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+#[require(
+    Mesh3d,
+    DynamicMaterial,
+    interact::Interactable = interact::Interactable::cuboid(Vec3::splat(0.5))
+)]
+pub struct Cube {
+    /// Faces picked from [`CubeAtlas`]; the material should use the atlas
+    /// texture. Without tiles the texture covers each face whole.
+    pub tiles: Option<CubeTiles>,
+}
+
+impl Cube {
+    pub fn tiled(tiles: CubeTiles) -> Self {
+        Cube { tiles: Some(tiles) }
+    }
+}
+
+/// Atlas tiles for each face of a cube, in the order +X, -X, +Y, -Y, +Z, -Z.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CubeTiles(pub [u16; 6]);
+
+impl CubeTiles {
+    /// One tile on top, one on the bottom and one on the four sides.
+    pub const fn pillar(top: u16, side: u16, bottom: u16) -> Self {
+        CubeTiles([side, side, top, bottom, side, side])
+    }
+}
+
+/// The texture atlas tiled cubes take their faces from, split into a grid of
+/// equal tiles numbered row by row.
+#[derive(Resource)]
+pub struct CubeAtlas {
+    pub texture: Handle<Image>,
+    /// Size of one tile, in texture pixels.
+    pub tile_size: UVec2,
+    /// Worked out from the texture size once it has loaded.
+    pub columns: u32,
+    pub rows: u32,
+}
+
+impl FromWorld for CubeAtlas {
+    fn from_world(world: &mut World) -> Self {
+        let assets = world.resource::<AssetServer>();
+        CubeAtlas {
+            texture: assets.load_with_settings("grid_set.png", |s: &mut ImageLoaderSettings| {
+                s.sampler = ImageSampler::nearest();
+            }),
+            tile_size: UVec2::ONE,
+            columns: 1,
+            rows: 1,
+        }
+    }
+}
+
+impl CubeAtlas {
+    /// The UV rectangle of `tile`.
+    pub fn tile_rect(&self, tile: u16) -> Rect {
+        let size = Vec2::new(1.0 / self.columns as f32, 1.0 / self.rows as f32);
+        let tile = tile as u32;
+        let min = UVec2::new(tile % self.columns, tile / self.columns).as_vec2() * size;
+        Rect::from_corners(min, min + size)
+    }
+}
+
+/// Split the atlas into tiles once its texture is known.
+fn size_atlas(
+    mut atlas: ResMut<CubeAtlas>,
+    mut events: EventReader<AssetEvent<Image>>,
+    images: Res<Assets<Image>>,
+) {
+    for event in events.read() {
+        if !event.is_loaded_with_dependencies(&atlas.texture) && !event.is_modified(&atlas.texture)
+        {
+            continue;
+        }
+        let Some(image) = images.get(&atlas.texture) else {
+            continue;
+        };
+        let tiles = (image.size() / atlas.tile_size.max(UVec2::ONE)).max(UVec2::ONE);
+        if (atlas.columns, atlas.rows) != (tiles.x, tiles.y) {
+            atlas.columns = tiles.x;
+            atlas.rows = tiles.y;
+        }
+    }
+}
+
+/// Meshes for tiled cubes drawn on their own, one per set of tiles.
+#[derive(Resource, Default)]
+struct TiledMeshes(HashMap<CubeTiles, Handle<Mesh>>);
 
 #[derive(Resource)]
 struct CubeTex(Option<Handle<Image>>);
@@ -28,27 +117,84 @@ impl Default for CubeTex {
     }
 }
 
+/// The grid placed cubes snap to. Cells have their corners on multiples of
+/// `size`, so they line up with the ground grid.
+#[derive(Resource)]
+pub struct CubeGrid {
+    pub size: f32,
+}
+
+impl Default for CubeGrid {
+    fn default() -> Self {
+        CubeGrid { size: 0.5 }
+    }
+}
+
+impl CubeGrid {
+    /// The cell containing `point`.
+    pub fn cell(&self, point: Vec3) -> IVec3 {
+        (point / self.size).floor().as_ivec3()
+    }
+
+    /// Center of the cell containing `point`.
+    pub fn cell_center(&self, point: Vec3) -> Vec3 {
+        (self.cell(point).as_vec3() + 0.5) * self.size
+    }
+}
+
+/// One cube mesh shared by every cube.
+#[derive(Resource)]
+pub struct CubeMesh(pub Handle<Mesh>);
+
+impl FromWorld for CubeMesh {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        CubeMesh(meshes.add(Cuboid::default()))
+    }
+}
+
 pub fn plugin(app: &mut App) {
     app.init_resource::<CubeTex>();
+    app.init_resource::<CubeGrid>();
+    app.init_resource::<CubeMesh>();
+    app.init_resource::<CubeAtlas>();
+    app.init_resource::<TiledMeshes>();
     app.add_systems(Startup, setup);
-    app.add_systems(Update, cube_click_detect);
+    app.add_systems(
+        Update,
+        (
+            (cube_click_detect, edit_cubes).chain(),
+            (size_atlas, mesh_tiled_cubes).chain(),
+        )
+            .after(interact::pick),
+    );
 }
 
 fn setup(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<FlatMaterial>>,
+    cube_mesh: Res<CubeMesh>,
+    atlas: Res<CubeAtlas>,
+    mut cache: ResMut<MaterialCache>,
+    mut f_mats: ResMut<Assets<FlatMaterial>>,
+    mut s_mats: ResMut<Assets<ShadedMaterial>>,
+    g_set: Res<GameSettings>,
     mut cube_tex: ResMut<CubeTex>,
     assets: Res<AssetServer>,
 ) {
     cube_tex.0 = Some(assets.load_with_settings("cube_tex.png", |s: &mut _| {
         *s = ImageLoaderSettings {
             // sampler: ImageSampler::nearest(),
+            // Merged chunk faces repeat the texture once per cell.
+            sampler: ImageSampler::Descriptor(ImageSamplerDescriptor {
+                address_mode_u: ImageAddressMode::Repeat,
+                address_mode_v: ImageAddressMode::Repeat,
+                ..default()
+            }),
             ..default()
         }
     }));
 
-    let cube_mesh = meshes.add(Cuboid::default());
+    let cube_mesh = cube_mesh.0.clone();
 
     // for i in -10..=10 {
     //     for j in -10..=10 {
@@ -58,83 +204,205 @@ fn setup(
     //                 color: LinearRgba::new(1.0, 0.0, 0.0, 1.0),
     //                 texture: Some(cube_tex.get_handle()),
     //                 alpha_mode: AlphaMode::Opaque,
+    //                 ..default()
     //             })),
     //             Transform::from_xyz(i as f32, 0.25, j as f32).with_scale(Vec3::splat(0.5)),
     //             Cube,
     //         ));
     //     }
     // }
-    commands.spawn((
-        Mesh3d::from(cube_mesh.clone()),
-        MeshMaterial3d(materials.add(FlatMaterial {
-            color: LinearRgba::new(1.0, 0.0, 0.0, 1.0),
-            texture: Some(cube_tex.get_handle()),
-            alpha_mode: AlphaMode::Opaque,
-        })),
-        Transform::from_xyz(1.0, 0.25, 0.0).with_scale(Vec3::splat(0.5)),
-        Cube,
-    ));
+    // Centered on a grid cell so it gets indexed, next to the tiled cube.
+    let red = cache.get(
+        &mut f_mats,
+        &mut s_mats,
+        Color::srgb(1.0, 0.0, 0.0),
+        Some(cube_tex.get_handle()),
+        AlphaMode::Opaque,
+    );
+    red.apply(
+        &mut commands.spawn((
+            Mesh3d::from(cube_mesh.clone()),
+            Transform::from_xyz(1.25, 0.25, 0.25).with_scale(Vec3::splat(0.5)),
+            Cube::default(),
+        )),
+        g_set.contains(GameSettings::FLAT),
+    );
+
+    let tiled = cache.get(
+        &mut f_mats,
+        &mut s_mats,
+        Color::WHITE,
+        Some(atlas.texture.clone()),
+        AlphaMode::Opaque,
+    );
+    tiled.apply(
+        &mut commands.spawn((
+            Transform::from_xyz(0.75, 0.25, 0.25).with_scale(Vec3::splat(0.5)),
+            Cube::tiled(CubeTiles::pillar(0, 1, 3)),
+        )),
+        g_set.contains(GameSettings::FLAT),
+    );
 }
 
 fn cube_click_detect(
-    mut s_mats: ResMut<Assets<StandardMaterial>>,
+    mut commands: Commands,
+    mut s_mats: ResMut<Assets<ShadedMaterial>>,
     mut f_mats: ResMut<Assets<FlatMaterial>>,
+    mut cache: ResMut<MaterialCache>,
+    mut rng: ResMut<GameRng>,
+    mut clicks: EventReader<interact::Clicked>,
     g_set: Res<GameSettings>,
-    players: Query<(&Transform, &ActionState<player::PlayerAction>), With<player::Player>>,
     cube_tex: Res<CubeTex>,
-    cubes: Query<
-        (
-            &Transform,
-            Option<&mut MeshMaterial3d<FlatMaterial>>,
-            Option<&mut MeshMaterial3d<StandardMaterial>>,
-        ),
-        (With<Cube>, With<DynamicMaterial>),
-    >,
+    cubes: Query<(), (With<Cube>, With<DynamicMaterial>)>,
 ) {
-    let (p_trans, action) = players.single().unwrap();
+    for interact::Clicked(hit) in clicks.read() {
+        if !cubes.contains(hit.entity) {
+            continue;
+        }
 
-    if !action.just_pressed(&player::PlayerAction::Click) {
+        let pair = cache.get(
+            &mut f_mats,
+            &mut s_mats,
+            // A handful of hues, so clicked cubes share materials.
+            Color::hsv(rng.gen_range(0..12) as f32 * 30.0, 1.0, 1.0),
+            Some(cube_tex.get_handle()),
+            AlphaMode::Opaque,
+        );
+        pair.apply(
+            &mut commands.entity(hit.entity),
+            g_set.contains(GameSettings::FLAT),
+        );
+    }
+}
+
+/// How much a cell is shrunk before checking it against the player.
+const CELL_MARGIN: f32 = 1e-3;
+
+/// Place a cube against the face being pointed at (or on the ground) and remove
+/// the cube being pointed at.
+fn edit_cubes(
+    mut commands: Commands,
+    mut s_mats: ResMut<Assets<ShadedMaterial>>,
+    mut f_mats: ResMut<Assets<FlatMaterial>>,
+    mut cache: ResMut<MaterialCache>,
+    hovered: Res<interact::Hovered>,
+    reach: Res<interact::PickReach>,
+    grid: Res<CubeGrid>,
+    g_set: Res<GameSettings>,
+    cube_tex: Res<CubeTex>,
+    cube_mesh: Res<CubeMesh>,
+    world: Res<CubeWorld>,
+    players: Query<(&Transform, &Player, &ActionState<PlayerAction>)>,
+    cubes: Query<&Transform, With<Cube>>,
+) {
+    let Ok((p_trans, player, action)) = players.single() else {
         return;
+    };
+    let hit = hovered.0.filter(|h| cubes.contains(h.entity));
+
+    if action.just_pressed(&PlayerAction::Remove)
+        && let Some(hit) = hit
+    {
+        commands.entity(hit.entity).despawn();
     }
 
-    let dir = p_trans.forward();
-    let ray = Ray::new(
-        Point::from(p_trans.translation.to_array()),
-        Vector3::from(dir.to_array()),
+    if !action.just_pressed(&PlayerAction::Place) {
+        return;
+    }
+
+    // The ground is not pickable, so intersect the view ray with it by hand.
+    let origin = p_trans.translation;
+    let dir = Vec3::from(p_trans.forward());
+    let ground = (dir.y < 0.0)
+        .then(|| -origin.y / dir.y)
+        .filter(|&t| t <= reach.0)
+        .map(|t| (t, origin + dir * t, Vec3::Y));
+    let face = hit.map(|h| (h.distance, h.point, h.normal));
+
+    let Some((_, point, normal)) = [face, ground]
+        .into_iter()
+        .flatten()
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+    else {
+        return;
+    };
+    // Step half a cell off the face so the point lands in the neighbouring cell.
+    let center = grid.cell_center(point + normal * grid.size * 0.5);
+
+    let taken = world.get(grid.cell(center)).is_some()
+        || world.loose().any(|e| {
+            cubes
+                .get(e)
+                .is_ok_and(|c| c.translation.distance_squared(center) < (grid.size * 0.5).powi(2))
+        });
+    if taken {
+        return;
+    }
+    // Shrunk a little so standing on or next to the new cube is still fine.
+    let cell = Aabb3d::new(center, Vec3::splat(grid.size * 0.5 - CELL_MARGIN));
+    if cell.intersects(&player.bounds(p_trans.translation)) {
+        return;
+    }
+
+    let pair = cache.get(
+        &mut f_mats,
+        &mut s_mats,
+        Color::srgb(1.0, 0.0, 0.0),
+        Some(cube_tex.get_handle()),
+        AlphaMode::Opaque,
+    );
+    pair.apply(
+        &mut commands.spawn((
+            Cube::default(),
+            Mesh3d(cube_mesh.0.clone()),
+            Transform::from_translation(center).with_scale(Vec3::splat(grid.size)),
+        )),
+        g_set.contains(GameSettings::FLAT),
     );
+}
 
-    for (c_trans, f_mat, s_mat) in cubes {
-        let sq = parry3d::shape::Cuboid::new(Vector3::from(c_trans.scale.to_array()) * 0.5);
-
-        let res = sq.cast_ray(
-            &Isometry::new(
-                Vector3::from(c_trans.translation.to_array()),
-                Vector3::from(c_trans.rotation.to_scaled_axis().to_array()),
-            ),
-            &ray,
-            50.0,
-            true,
-        );
-        if let Some(_) = res {
-            if g_set.contains(GameSettings::FLAT)
-                && let Some(mut mat) = f_mat
-            {
-                mat.0 = f_mats.add(FlatMaterial {
-                    color: Color::hsv(random::<f32>() * 360.0, 1.0, 1.0).into(),
-                    alpha_mode: AlphaMode::Opaque,
-                    texture: Some(cube_tex.get_handle()),
-                });
-            }
-
-            if !g_set.contains(GameSettings::FLAT)
-                && let Some(mut mat) = s_mat
-            {
-                mat.0 = s_mats.add(StandardMaterial {
-                    base_color: Color::hsv(random::<f32>() * 360.0, 1.0, 1.0),
-                    base_color_texture: Some(cube_tex.get_handle()),
-                    ..default()
-                });
-            }
+fn mesh_tiled_cubes(
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut tiled: ResMut<TiledMeshes>,
+    atlas: Res<CubeAtlas>,
+    mut cubes: Query<(Ref<Cube>, &mut Mesh3d)>,
+) {
+    // Tile positions moved, so every mesh is stale.
+    if atlas.is_changed() {
+        tiled.0.clear();
+    }
+    for (cube, mut mesh) in &mut cubes {
+        let Some(tiles) = cube.tiles else {
+            continue;
+        };
+        if !cube.is_changed() && !atlas.is_changed() {
+            continue;
         }
+        mesh.0 = tiled
+            .0
+            .entry(tiles)
+            .or_insert_with(|| meshes.add(tiled_cube_mesh(tiles, &atlas)))
+            .clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_cells() {
+        let grid = CubeGrid { size: 0.5 };
+        assert_eq!(grid.cell(Vec3::new(0.1, 0.1, 0.1)), IVec3::ZERO);
+        assert_eq!(grid.cell(Vec3::new(0.5, 1.0, 0.75)), IVec3::new(1, 2, 1));
+        // Floored, not truncated, below zero.
+        assert_eq!(
+            grid.cell(Vec3::new(-0.1, -0.5, -0.6)),
+            IVec3::new(-1, -1, -2)
+        );
+        assert_eq!(
+            grid.cell_center(Vec3::new(-0.1, 0.1, 0.6)),
+            Vec3::new(-0.25, 0.25, 0.75)
+        );
     }
 }
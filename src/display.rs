@@ -1,13 +1,14 @@
 use crate::GameSize;
+use crate::post::{PostChain, PostMaterial};
 use bevy::asset::RenderAssetUsages;
 use bevy::image::ImageSampler;
+use bevy::input::common_conditions::input_just_pressed;
 use bevy::prelude::*;
 use bevy::render::camera::RenderTarget;
 use bevy::render::render_resource::{
-    AsBindGroup, ShaderRef, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+    Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
 };
 use bevy::render::view::RenderLayers;
-use bevy::sprite::{Material2d, Material2dPlugin};
 use bevy::window::{PrimaryWindow, WindowRef};
 
 #[derive(Resource)]
@@ -30,24 +31,110 @@ impl Default for RenderTex {
 #[require(Mesh2d)]
 struct RenderQuad;
 
-#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
-struct QuantizerMaterial {
-    #[texture(0)]
-    #[sampler(1)]
-    texture: Option<Handle<Image>>,
+/// What `GameSize` follows. Change it at runtime (F8 cycles through
+/// [`RESOLUTIONS`]) and the render texture is rebuilt and every camera drawing
+/// into it retargeted.
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub enum Resolution {
+    Fixed(UVec2),
+    /// `height` game pixels high and as wide as the window's aspect allows.
+    MatchWindow {
+        height: u32,
+    },
 }
 
-impl Material2d for QuantizerMaterial {
-    fn fragment_shader() -> ShaderRef {
-        "color_quant.wgsl".into()
+pub const RESOLUTIONS: [Resolution; 4] = [
+    Resolution::Fixed(UVec2::new(320, 240)),
+    Resolution::Fixed(UVec2::new(426, 240)),
+    Resolution::Fixed(UVec2::new(640, 480)),
+    Resolution::MatchWindow { height: 240 },
+];
+
+impl Resolution {
+    fn size(self, window: &Window) -> Extent3d {
+        let size = match self {
+            Resolution::Fixed(size) => size,
+            Resolution::MatchWindow { height } => {
+                let aspect = window.width() / window.height().max(1.0);
+                UVec2::new((height as f32 * aspect).round() as u32, height)
+            }
+        }
+        .max(UVec2::ONE);
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        }
     }
 }
 
+/// How the render texture is scaled to the window. F10 cycles through them.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DisplayScale {
+    /// The largest whole number of window pixels per game pixel that fits, so
+    /// every game pixel is the same size. Letterboxed.
+    #[default]
+    Integer,
+    /// The largest size that fits, keeping the aspect. Letterboxed.
+    Fit,
+    /// Fill the window, ignoring the aspect.
+    Stretch,
+    /// Fill the window keeping the aspect, cutting off what doesn't fit.
+    Crop,
+}
+
+/// What shows around the game where it doesn't cover the window.
+#[derive(Resource, Clone, Debug)]
+pub struct Letterbox {
+    pub color: Color,
+    /// Stretched over the whole window, behind the game.
+    pub image: Option<Handle<Image>>,
+}
+
+impl Default for Letterbox {
+    fn default() -> Self {
+        Letterbox {
+            color: Color::BLACK,
+            image: None,
+        }
+    }
+}
+
+/// The window camera drawing the display quad.
+#[derive(Component)]
+struct DisplayCamera;
+
+#[derive(Component)]
+struct LetterboxImage;
+
+/// Both display quad materials, so switching the post chain on and off is a swap.
+#[derive(Resource)]
+struct DisplayMaterials {
+    plain: Handle<ColorMaterial>,
+    post: Handle<PostMaterial>,
+}
+
 pub fn plugin(app: &mut App) {
     app.init_resource::<RenderTex>();
-    app.add_plugins(Material2dPlugin::<QuantizerMaterial>::default());
+    app.init_resource::<DisplayScale>();
+    app.init_resource::<Letterbox>();
     app.add_systems(PreStartup, setup); // Important to get texture before init other resources
-    app.add_systems(Update, resize);
+    app.add_systems(
+        Update,
+        (
+            cycle_resolution.run_if(input_just_pressed(KeyCode::F8)),
+            follow_resolution,
+            rebuild_render_tex.run_if(resource_changed::<GameSize>),
+            cycle_display_scale.run_if(input_just_pressed(KeyCode::F10)),
+            resize,
+            set_letterbox.run_if(resource_changed::<Letterbox>),
+        )
+            .chain(),
+    );
+    app.add_systems(
+        PostUpdate,
+        set_quad_material.run_if(resource_changed::<PostChain>),
+    );
 }
 
 fn setup(
@@ -55,25 +142,64 @@ fn setup(
     mut render_tex: ResMut<RenderTex>,
     mut images: ResMut<Assets<Image>>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut q_material: ResMut<Assets<QuantizerMaterial>>,
+    mut p_material: ResMut<Assets<PostMaterial>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
-    game_size: Res<GameSize>,
+    chain: Res<PostChain>,
+    resolution: Res<Resolution>,
     windows: Query<&Window, With<PrimaryWindow>>,
-    mut g_set: ResMut<crate::GameSettings>,
 ) {
     let window = windows.single().unwrap();
+    let game_size = GameSize(resolution.size(window));
+
+    render_tex.0 = Some(images.add(render_image(game_size.0)));
+
+    commands.spawn((
+        Camera2d,
+        Camera {
+            target: RenderTarget::Window(WindowRef::Primary),
+            clear_color: ClearColorConfig::Custom(Color::BLACK),
+            ..default()
+        },
+        RenderLayers::layer(1),
+        DisplayCamera,
+    ));
 
-    let mut render_target = Image::new_fill(
-        game_size.0,
+    // Sized by `resize`.
+    commands.spawn((
+        RenderQuad,
+        Mesh2d(meshes.add(Rectangle::default())),
+        Transform::from_translation(Vec3::Z * 5.0),
+        RenderLayers::layer(1),
+    ));
+    commands.spawn((
+        LetterboxImage,
+        Sprite::default(),
+        Visibility::Hidden,
+        RenderLayers::layer(1),
+    ));
+    commands.insert_resource(DisplayMaterials {
+        plain: materials.add(ColorMaterial {
+            texture: Some(render_tex.get_handle()),
+            ..default()
+        }),
+        post: p_material.add(PostMaterial::new(render_tex.get_handle(), &chain)),
+    });
+    commands.insert_resource(game_size);
+}
+
+/// An empty render target for a camera to draw into.
+pub fn render_image(size: Extent3d) -> Image {
+    let mut image = Image::new_fill(
+        size,
         TextureDimension::D2,
         &[0, 0, 0, 0],
         TextureFormat::Bgra8UnormSrgb,
         RenderAssetUsages::default(),
     );
 
-    render_target.texture_descriptor = TextureDescriptor {
+    image.texture_descriptor = TextureDescriptor {
         label: None,
-        size: game_size.0,
+        size,
         mip_level_count: 1,
         sample_count: 1,
         dimension: TextureDimension::D2,
@@ -84,67 +210,157 @@ fn setup(
         view_formats: &[TextureFormat::Bgra8UnormSrgb],
     };
 
-    render_target.sampler = ImageSampler::nearest();
+    image.sampler = ImageSampler::nearest();
+    image
+}
 
-    render_tex.0 = Some(images.add(render_target));
+fn set_quad_material(
+    mut commands: Commands,
+    quad: Query<Entity, With<RenderQuad>>,
+    mats: Res<DisplayMaterials>,
+    chain: Res<PostChain>,
+) {
+    let Ok(quad) = quad.single() else {
+        return;
+    };
+    let mut quad = commands.entity(quad);
+    if chain.any_enabled() {
+        quad.remove::<MeshMaterial2d<ColorMaterial>>()
+            .insert(MeshMaterial2d(mats.post.clone()));
+    } else {
+        quad.remove::<MeshMaterial2d<PostMaterial>>()
+            .insert(MeshMaterial2d(mats.plain.clone()));
+    }
+}
 
-    commands.spawn((
-        Camera2d,
-        Camera {
-            target: RenderTarget::Window(WindowRef::Primary),
-            clear_color: ClearColorConfig::Custom(Color::BLACK),
-            ..default()
-        },
-        RenderLayers::layer(1),
-    ));
+fn cycle_resolution(mut resolution: ResMut<Resolution>) {
+    let next = RESOLUTIONS
+        .iter()
+        .position(|r| *r == *resolution)
+        .map_or(0, |i| (i + 1) % RESOLUTIONS.len());
+    *resolution = RESOLUTIONS[next];
+}
 
-    let scale = f32::min(
-        window.width() / game_size.0.width as f32,
-        window.height() / game_size.0.height as f32,
-    );
+/// Keep `GameSize` on the resolution, which may follow the window.
+fn follow_resolution(
+    resolution: Res<Resolution>,
+    mut game_size: ResMut<GameSize>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+) {
+    let Ok(window) = window_query.single() else {
+        return;
+    };
+    let size = resolution.size(window);
+    if game_size.0 != size {
+        game_size.0 = size;
+    }
+}
 
-    let mut display = commands.spawn((
-        RenderQuad,
-        Mesh2d(meshes.add(Rectangle::default())),
-        Transform {
-            scale: Vec3::new(scale, scale, 0.0),
-            rotation: Quat::IDENTITY,
-            translation: Vec3::Z * 5.0,
-        },
-        RenderLayers::layer(1),
-    ));
-    if !g_set.contains(crate::GameSettings::COLOR_QUANTIZE) {
-        display.insert(MeshMaterial2d(materials.add(ColorMaterial {
-            texture: Some(render_tex.get_handle()),
-            ..default()
-        })));
-        // info!("Defaulting to ColorMaterial!");
-        g_set.set(crate::GameSettings::COLOR_QUANTIZE, false);
-    } else {
-        display.insert(MeshMaterial2d(q_material.add(QuantizerMaterial {
-            texture: Some(render_tex.get_handle()),
-        })));
-        // info!("Quantizer Material picked!");
-        g_set.set(crate::GameSettings::COLOR_QUANTIZE, true);
+/// Swap the render texture for one of the new `GameSize`, pointing every
+/// camera and display material that used the old one at it.
+fn rebuild_render_tex(
+    mut render_tex: ResMut<RenderTex>,
+    mut images: ResMut<Assets<Image>>,
+    mut cameras: Query<(&mut Camera, Option<&mut Projection>)>,
+    mats: Res<DisplayMaterials>,
+    mut p_material: ResMut<Assets<PostMaterial>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    game_size: Res<GameSize>,
+) {
+    let old = render_tex.get_handle();
+    if images.get(&old).map(|i| i.texture_descriptor.size) == Some(game_size.0) {
+        return;
     }
+    let new = images.add(render_image(game_size.0));
+    render_tex.0 = Some(new.clone());
+
+    for (mut camera, projection) in &mut cameras {
+        let RenderTarget::Image(target) = &mut camera.target else {
+            continue;
+        };
+        if target.handle != old {
+            continue;
+        }
+        target.handle = new.clone();
+        // Bevy also does this once the new target is extracted, a frame late.
+        if let Some(mut projection) = projection
+            && let Projection::Perspective(p) = projection.as_mut()
+        {
+            p.aspect_ratio = game_size.0.width as f32 / game_size.0.height as f32;
+        }
+    }
+
+    if let Some(mat) = p_material.get_mut(&mats.post) {
+        mat.texture = Some(new.clone());
+    }
+    if let Some(mat) = materials.get_mut(&mats.plain) {
+        mat.texture = Some(new);
+    }
+}
+
+fn cycle_display_scale(mut scale: ResMut<DisplayScale>) {
+    *scale = match *scale {
+        DisplayScale::Integer => DisplayScale::Fit,
+        DisplayScale::Fit => DisplayScale::Stretch,
+        DisplayScale::Stretch => DisplayScale::Crop,
+        DisplayScale::Crop => DisplayScale::Integer,
+    };
 }
 
 fn resize(
     mut quad_query: Query<&mut Transform, With<RenderQuad>>,
+    mut letterbox_query: Query<&mut Sprite, With<LetterboxImage>>,
     window_query: Query<&Window, With<PrimaryWindow>>,
     game_size: Res<GameSize>,
+    display_scale: Res<DisplayScale>,
 ) {
     let window = window_query.single().unwrap();
     let mut quad = quad_query.single_mut().unwrap();
 
-    let scale = f32::min(
-        window.width() / game_size.0.width as f32,
-        window.height() / game_size.0.height as f32,
-    );
+    // Work in physical pixels so integer scaling stays exact on HiDPI screens.
+    let window_size = window.physical_size().as_vec2();
+    let game = Vec2::new(game_size.0.width as f32, game_size.0.height as f32);
+    let ratio = window_size / game;
 
-    quad.scale = Vec3::new(
-        game_size.0.width as f32 * scale,
-        game_size.0.height as f32 * scale,
-        1.0,
-    );
+    let size = match *display_scale {
+        DisplayScale::Integer => game * ratio.min_element().floor().max(1.0),
+        DisplayScale::Fit => game * ratio.min_element(),
+        DisplayScale::Stretch => window_size,
+        DisplayScale::Crop => game * ratio.max_element(),
+    };
+
+    // Odd leftovers would put the quad edges, and every game pixel, halfway
+    // between window pixels.
+    let offset = match *display_scale {
+        DisplayScale::Integer => ((window_size - size) % 2.0) * 0.5,
+        _ => Vec2::ZERO,
+    };
+
+    let to_logical = 1.0 / window.scale_factor();
+    quad.scale = (size * to_logical).extend(1.0);
+    quad.translation.x = offset.x * to_logical;
+    quad.translation.y = offset.y * to_logical;
+
+    if let Ok(mut sprite) = letterbox_query.single_mut() {
+        sprite.custom_size = Some(window.size());
+    }
+}
+
+fn set_letterbox(
+    letterbox: Res<Letterbox>,
+    mut camera_query: Query<&mut Camera, With<DisplayCamera>>,
+    mut image_query: Query<(&mut Sprite, &mut Visibility), With<LetterboxImage>>,
+) {
+    for mut camera in &mut camera_query {
+        camera.clear_color = ClearColorConfig::Custom(letterbox.color);
+    }
+    for (mut sprite, mut visibility) in &mut image_query {
+        match &letterbox.image {
+            Some(image) => {
+                sprite.image = image.clone();
+                *visibility = Visibility::Inherited;
+            }
+            None => *visibility = Visibility::Hidden,
+        }
+    }
 }
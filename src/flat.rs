@@ -1,27 +1,105 @@
 use crate::GameSettings;
+use bevy::asset::UntypedAssetId;
+use bevy::math::Affine2;
+use bevy::pbr::{ExtendedMaterial, MaterialExtension};
+use bevy::platform::collections::HashMap;
 use bevy::prelude::*;
-use bevy::render::render_resource::{AsBindGroup, ShaderRef};
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{AsBindGroup, AsBindGroupShaderType, ShaderRef, ShaderType};
+use bevy::render::texture::GpuImage;
 
 pub fn plugin(app: &mut App) {
-    app.add_plugins(MaterialPlugin::<FlatMaterial>::default());
+    app.add_plugins((
+        MaterialPlugin::<FlatMaterial>::default(),
+        MaterialPlugin::<ShadedMaterial>::default(),
+    ));
+    app.init_resource::<MaterialCache>();
     app.add_systems(
         PreUpdate,
         set_materials.run_if(resource_changed::<GameSettings>),
     );
+    app.add_systems(Last, prune_cache);
 }
 
 /// A simple material to render the texture to an object with color.
 #[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+#[uniform(2, FlatMaterialUniform)]
 pub struct FlatMaterial {
     #[texture(0)]
     #[sampler(1)]
     pub texture: Option<Handle<Image>>,
-    #[uniform(2)]
     pub color: LinearRgba,
+    /// UV offset (xy) and scale (zw), used to pick a cell out of a sprite sheet.
+    pub uv_rect: Vec4,
+    /// `AlphaMode::Mask` discards pixels below the cutoff and writes depth.
     pub alpha_mode: AlphaMode,
+    /// Added to the camera distance when sorting blended materials. Keep it
+    /// within [`MAX_SORT_BIAS`] so the standard version sorts the same.
+    pub depth_bias: f32,
+}
+
+/// The GPU side of a [`FlatMaterial`], kept as one uniform for WebGL2.
+#[derive(Clone, Default, ShaderType)]
+pub struct FlatMaterialUniform {
+    pub color: Vec4,
+    pub uv_rect: Vec4,
+    pub alpha_cutoff: f32,
+}
+
+impl AsBindGroupShaderType<FlatMaterialUniform> for FlatMaterial {
+    fn as_bind_group_shader_type(&self, _images: &RenderAssets<GpuImage>) -> FlatMaterialUniform {
+        let alpha_cutoff = match self.alpha_mode {
+            AlphaMode::Mask(cutoff) => cutoff,
+            // Nothing has negative alpha, so this never discards.
+            _ => -1.0,
+        };
+
+        FlatMaterialUniform {
+            color: self.color.to_vec4(),
+            uv_rect: self.uv_rect,
+            alpha_cutoff,
+        }
+    }
+}
+
+impl Default for FlatMaterial {
+    fn default() -> Self {
+        FlatMaterial {
+            texture: None,
+            color: LinearRgba::WHITE,
+            uv_rect: FlatMaterial::FULL_UV,
+            alpha_mode: AlphaMode::Opaque,
+            depth_bias: 0.0,
+        }
+    }
+}
+
+impl FlatMaterial {
+    /// A `uv_rect` covering the whole texture.
+    pub const FULL_UV: Vec4 = Vec4::new(0.0, 0.0, 1.0, 1.0);
+}
+
+/// Convert a flat `uv_rect` into the equivalent `StandardMaterial::uv_transform`.
+pub fn uv_rect_to_transform(uv_rect: Vec4) -> Affine2 {
+    Affine2::from_scale_angle_translation(uv_rect.zw(), 0.0, uv_rect.xy())
+}
+
+/// Convert a `StandardMaterial::uv_transform` back into a flat `uv_rect`.
+/// Rotation and shear are dropped.
+pub fn uv_transform_to_rect(uv_transform: Affine2) -> Vec4 {
+    Vec4::new(
+        uv_transform.translation.x,
+        uv_transform.translation.y,
+        uv_transform.matrix2.x_axis.x,
+        uv_transform.matrix2.y_axis.y,
+    )
 }
 
 impl Material for FlatMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "flat.wgsl".into()
+    }
+
     fn fragment_shader() -> ShaderRef {
         "flat.wgsl".into()
     }
@@ -29,47 +107,295 @@ impl Material for FlatMaterial {
     fn alpha_mode(&self) -> AlphaMode {
         self.alpha_mode
     }
+
+    fn depth_bias(&self) -> f32 {
+        self.depth_bias
+    }
 }
 
+/// The shaded mode material: a `StandardMaterial` whose vertex shader turns
+/// billboards towards each view the way [`FlatMaterial`] does.
+pub type ShadedMaterial = ExtendedMaterial<StandardMaterial, BillboardFacing>;
+
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone, Default)]
+pub struct BillboardFacing {}
+
+impl MaterialExtension for BillboardFacing {
+    fn vertex_shader() -> ShaderRef {
+        "shaded.wgsl".into()
+    }
+}
+
+pub fn shaded(base: StandardMaterial) -> ShadedMaterial {
+    ShadedMaterial {
+        base,
+        extension: BillboardFacing {},
+    }
+}
+
+/// Largest `depth_bias` to put on these materials. `StandardMaterial` also
+/// turns its `depth_bias` into a hardware depth bias of `depth_bias as i32`,
+/// which this keeps at 0, so in both modes it only changes the sort order.
+pub const MAX_SORT_BIAS: f32 = 0.99;
+
 /// A flag component to enable shaded / flat shader dynamics
 #[derive(Component, Default)]
 pub struct DynamicMaterial;
 
+/// The flat and standard versions of an entity's material. Switching modes
+/// swaps between them instead of building a new material each time.
+#[derive(Component, Clone, Debug)]
+pub struct MaterialPair {
+    pub flat: Handle<FlatMaterial>,
+    pub standard: Handle<ShadedMaterial>,
+}
+
+impl MaterialPair {
+    /// Put the material for the current mode on `entity` and remember both.
+    pub fn apply(self, entity: &mut EntityCommands, flat: bool) {
+        // The entity may be despawned by an earlier command this frame.
+        if flat {
+            entity
+                .remove::<MeshMaterial3d<ShadedMaterial>>()
+                .try_insert(MeshMaterial3d(self.flat.clone()));
+        } else {
+            entity
+                .remove::<MeshMaterial3d<FlatMaterial>>()
+                .try_insert(MeshMaterial3d(self.standard.clone()));
+        }
+        entity.try_insert(self);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct MaterialKey {
+    color: [u32; 4],
+    texture: Option<AssetId<Image>>,
+    alpha_mode: (u8, u32),
+}
+
+impl MaterialKey {
+    fn new(color: LinearRgba, texture: Option<&Handle<Image>>, alpha_mode: AlphaMode) -> Self {
+        let alpha_mode = match alpha_mode {
+            AlphaMode::Opaque => (0, 0),
+            AlphaMode::Mask(cutoff) => (1, cutoff.to_bits()),
+            AlphaMode::Blend => (2, 0),
+            AlphaMode::Premultiplied => (3, 0),
+            AlphaMode::AlphaToCoverage => (4, 0),
+            AlphaMode::Add => (5, 0),
+            AlphaMode::Multiply => (6, 0),
+        };
+        MaterialKey {
+            color: color.to_f32_array().map(f32::to_bits),
+            texture: texture.map(|t| t.id()),
+            alpha_mode,
+        }
+    }
+}
+
+/// Shared material pairs for entities that only differ by color, texture and
+/// alpha mode.
+///
+/// Only ids are kept, so materials nobody uses any more are still freed. Don't
+/// edit a shared material in place; ask for the new look instead, or go
+/// through [`own_material`].
+#[derive(Resource, Default)]
+pub struct MaterialCache(HashMap<MaterialKey, (AssetId<FlatMaterial>, AssetId<ShadedMaterial>)>);
+
+impl MaterialCache {
+    pub fn get(
+        &mut self,
+        f_mats: &mut Assets<FlatMaterial>,
+        s_mats: &mut Assets<ShadedMaterial>,
+        color: Color,
+        texture: Option<Handle<Image>>,
+        alpha_mode: AlphaMode,
+    ) -> MaterialPair {
+        let key = MaterialKey::new(color.into(), texture.as_ref(), alpha_mode);
+        if let Some((f, s)) = self.0.get(&key)
+            && let Some(flat) = f_mats.get_strong_handle(*f)
+            && let Some(standard) = s_mats.get_strong_handle(*s)
+        {
+            return MaterialPair { flat, standard };
+        }
+
+        let flat = FlatMaterial {
+            color: color.into(),
+            texture,
+            alpha_mode,
+            ..default()
+        };
+        let mut standard = StandardMaterial {
+            unlit: true,
+            ..default()
+        };
+        copy_to_standard(&flat, &mut standard);
+
+        let pair = MaterialPair {
+            flat: f_mats.add(flat),
+            standard: s_mats.add(shaded(standard)),
+        };
+        self.0.insert(key, (pair.flat.id(), pair.standard.id()));
+        pair
+    }
+
+    /// Whether the material belongs to a cached pair, so others may use it too.
+    pub fn holds(&self, id: impl Into<UntypedAssetId>) -> bool {
+        let id = id.into();
+        self.0
+            .values()
+            .any(|(f, s)| f.untyped() == id || s.untyped() == id)
+    }
+}
+
+/// The entity's material, ready to edit in place. A cached material may be
+/// shared, so it is first swapped for a copy of the entity's own.
+pub fn own_material<'a, M: Material + Clone>(
+    commands: &mut Commands,
+    cache: &MaterialCache,
+    entity: Entity,
+    handle: &Handle<M>,
+    mats: &'a mut Assets<M>,
+) -> Option<&'a mut M> {
+    if !cache.holds(handle.id()) {
+        return mats.get_mut(handle);
+    }
+    let copy = mats.add(mats.get(handle)?.clone());
+    commands
+        .entity(entity)
+        .try_insert(MeshMaterial3d(copy.clone()));
+    mats.get_mut(&copy)
+}
+
+/// Forget cached pairs once their materials have been freed.
+fn prune_cache(
+    mut cache: ResMut<MaterialCache>,
+    mut events: EventReader<AssetEvent<FlatMaterial>>,
+    f_mats: Res<Assets<FlatMaterial>>,
+    s_mats: Res<Assets<ShadedMaterial>>,
+) {
+    if events
+        .read()
+        .any(|e| matches!(e, AssetEvent::Removed { .. }))
+    {
+        cache
+            .0
+            .retain(|_, (f, s)| f_mats.contains(*f) && s_mats.contains(*s));
+    }
+}
+
+fn copy_to_flat(src: &StandardMaterial, dst: &mut FlatMaterial) {
+    dst.color = src.base_color.into();
+    dst.texture = src.base_color_texture.clone();
+    dst.uv_rect = uv_transform_to_rect(src.uv_transform);
+    dst.alpha_mode = src.alpha_mode;
+    dst.depth_bias = src.depth_bias;
+}
+
+fn copy_to_standard(src: &FlatMaterial, dst: &mut StandardMaterial) {
+    dst.base_color = src.color.into();
+    dst.base_color_texture = src.texture.clone();
+    dst.uv_transform = uv_rect_to_transform(src.uv_rect);
+    dst.alpha_mode = src.alpha_mode;
+    dst.depth_bias = src.depth_bias.clamp(-MAX_SORT_BIAS, MAX_SORT_BIAS);
+}
+
+/// Whether the two halves of a pair still look the same.
+fn same_look(flat: &FlatMaterial, standard: &StandardMaterial) -> bool {
+    flat.color == standard.base_color.to_linear()
+        && flat.texture == standard.base_color_texture
+        && flat.uv_rect == uv_transform_to_rect(standard.uv_transform)
+        && flat.alpha_mode == standard.alpha_mode
+        && flat.depth_bias.clamp(-MAX_SORT_BIAS, MAX_SORT_BIAS) == standard.depth_bias
+}
+
 pub fn set_materials(
     mut commands: Commands,
+    mut cache: ResMut<MaterialCache>,
     mut f_mats: ResMut<Assets<FlatMaterial>>,
-    mut s_mats: ResMut<Assets<StandardMaterial>>,
-    std_entities: Query<(Entity, &MeshMaterial3d<StandardMaterial>), With<DynamicMaterial>>,
-    flat_entities: Query<(Entity, &MeshMaterial3d<FlatMaterial>), With<DynamicMaterial>>,
+    mut s_mats: ResMut<Assets<ShadedMaterial>>,
+    std_entities: Query<
+        (
+            Entity,
+            &MeshMaterial3d<ShadedMaterial>,
+            Option<&MaterialPair>,
+        ),
+        With<DynamicMaterial>,
+    >,
+    flat_entities: Query<
+        (Entity, &MeshMaterial3d<FlatMaterial>, Option<&MaterialPair>),
+        With<DynamicMaterial>,
+    >,
     g_set: Res<GameSettings>,
 ) {
+    // The current half may have been edited in place (sprite sheets, sort
+    // order) since the pair was made. Then the other half is not touched, as
+    // it may be shared, and a pair with the new look is used instead.
     if g_set.contains(GameSettings::FLAT) {
-        for (e, mat) in std_entities {
-            let o_mat = s_mats.get(mat.id()).unwrap();
-            let n_mat = MeshMaterial3d(f_mats.add(FlatMaterial {
-                color: o_mat.base_color.clone().into(),
-                texture: o_mat.base_color_texture.clone(),
-                alpha_mode: o_mat.alpha_mode,
-            }));
-            commands
-                .entity(e)
-                .remove::<MeshMaterial3d<StandardMaterial>>()
-                .insert(n_mat);
+        for (e, mat, pair) in std_entities {
+            let o_mat = s_mats.get(mat.id()).unwrap().base.clone();
+            let pair = match pair.filter(|p| p.standard == mat.0) {
+                Some(pair)
+                    if f_mats
+                        .get(pair.flat.id())
+                        .is_some_and(|f| same_look(f, &o_mat)) =>
+                {
+                    pair.clone()
+                }
+                _ if uv_transform_to_rect(o_mat.uv_transform) == FlatMaterial::FULL_UV
+                    && o_mat.depth_bias == 0.0 =>
+                {
+                    cache.get(
+                        &mut f_mats,
+                        &mut s_mats,
+                        o_mat.base_color,
+                        o_mat.base_color_texture.clone(),
+                        o_mat.alpha_mode,
+                    )
+                }
+                _ => {
+                    let mut f_mat = FlatMaterial::default();
+                    copy_to_flat(&o_mat, &mut f_mat);
+                    MaterialPair {
+                        flat: f_mats.add(f_mat),
+                        standard: mat.0.clone(),
+                    }
+                }
+            };
+            pair.apply(&mut commands.entity(e), true);
         }
     } else {
-        for (e, mat) in flat_entities {
-            let o_mat = f_mats.get(mat.id()).unwrap();
-            let n_mat = MeshMaterial3d(s_mats.add(StandardMaterial {
-                base_color: o_mat.color.clone().into(),
-                base_color_texture: o_mat.texture.clone(),
-                alpha_mode: o_mat.alpha_mode,
-                unlit: true,
-                ..default()
-            }));
-            commands
-                .entity(e)
-                .remove::<MeshMaterial3d<FlatMaterial>>()
-                .insert(n_mat);
+        for (e, mat, pair) in flat_entities {
+            let o_mat = f_mats.get(mat.id()).unwrap().clone();
+            let pair = match pair.filter(|p| p.flat == mat.0) {
+                Some(pair)
+                    if s_mats
+                        .get(pair.standard.id())
+                        .is_some_and(|s| same_look(&o_mat, &s.base)) =>
+                {
+                    pair.clone()
+                }
+                _ if o_mat.uv_rect == FlatMaterial::FULL_UV && o_mat.depth_bias == 0.0 => cache
+                    .get(
+                        &mut f_mats,
+                        &mut s_mats,
+                        o_mat.color.into(),
+                        o_mat.texture.clone(),
+                        o_mat.alpha_mode,
+                    ),
+                _ => {
+                    let mut s_mat = StandardMaterial {
+                        unlit: true,
+                        ..default()
+                    };
+                    copy_to_standard(&o_mat, &mut s_mat);
+                    MaterialPair {
+                        flat: mat.0.clone(),
+                        standard: s_mats.add(shaded(s_mat)),
+                    }
+                }
+            };
+            pair.apply(&mut commands.entity(e), false);
         }
     }
 }
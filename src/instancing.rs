@@ -0,0 +1,128 @@
+use crate::billboard::Billboard;
+use crate::flat::FlatMaterial;
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{AsBindGroup, AsBindGroupShaderType, ShaderRef, ShaderType};
+use bevy::render::texture::GpuImage;
+
+pub fn plugin(app: &mut App) {
+    app.add_plugins(MaterialPlugin::<BillboardMaterial> {
+        prepass_enabled: false,
+        shadows_enabled: false,
+        ..default()
+    });
+    app.init_resource::<InstancedBatches>();
+    app.add_systems(Update, attach_instanced);
+}
+
+/// Draws a [`Billboard`] through the instanced path.
+///
+/// Facing and spin happen in the vertex shader from the board's mesh tag,
+/// translation and scale, and boards with the same texture and cutoff share
+/// one mesh and material so they are drawn in one batch. Changing the texture
+/// or cutoff moves the board to the matching batch. The CPU facing skips
+/// these boards, and they cannot be picked.
+#[derive(Component, Clone, Debug)]
+#[require(Billboard)]
+pub struct InstancedBillboard {
+    pub texture: Handle<Image>,
+    /// Alpha below this is cut out. Instanced boards are never blended, so
+    /// they need no sorting.
+    pub cutoff: f32,
+}
+
+impl InstancedBillboard {
+    pub fn new(texture: Handle<Image>) -> Self {
+        InstancedBillboard {
+            texture,
+            cutoff: 0.5,
+        }
+    }
+}
+
+/// A [`FlatMaterial`] that turns the quad towards the camera on the GPU.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+#[uniform(2, BillboardMaterialUniform)]
+pub struct BillboardMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    pub texture: Option<Handle<Image>>,
+    pub color: LinearRgba,
+    pub uv_rect: Vec4,
+    pub cutoff: f32,
+}
+
+#[derive(Clone, Default, ShaderType)]
+pub struct BillboardMaterialUniform {
+    pub color: Vec4,
+    pub uv_rect: Vec4,
+    pub alpha_cutoff: f32,
+}
+
+impl AsBindGroupShaderType<BillboardMaterialUniform> for BillboardMaterial {
+    fn as_bind_group_shader_type(
+        &self,
+        _images: &RenderAssets<GpuImage>,
+    ) -> BillboardMaterialUniform {
+        BillboardMaterialUniform {
+            color: self.color.to_vec4(),
+            uv_rect: self.uv_rect,
+            alpha_cutoff: self.cutoff,
+        }
+    }
+}
+
+impl Material for BillboardMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "billboard.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "billboard.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Mask(self.cutoff)
+    }
+}
+
+/// Shared quad and one material per batch of instanced billboards.
+#[derive(Resource, Default)]
+struct InstancedBatches {
+    quad: Option<Handle<Mesh>>,
+    materials: HashMap<(AssetId<Image>, u32), Handle<BillboardMaterial>>,
+}
+
+fn attach_instanced(
+    mut commands: Commands,
+    mut batches: ResMut<InstancedBatches>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<BillboardMaterial>>,
+    boards: Query<(Entity, &InstancedBillboard), Changed<InstancedBillboard>>,
+) {
+    for (e, inst) in &boards {
+        let quad = batches
+            .quad
+            .get_or_insert_with(|| meshes.add(Rectangle::new(1.0, 1.0)))
+            .clone();
+
+        let key = (inst.texture.id(), inst.cutoff.to_bits());
+        let material = batches
+            .materials
+            .entry(key)
+            .or_insert_with(|| {
+                materials.add(BillboardMaterial {
+                    texture: Some(inst.texture.clone()),
+                    color: LinearRgba::WHITE,
+                    uv_rect: FlatMaterial::FULL_UV,
+                    cutoff: inst.cutoff,
+                })
+            })
+            .clone();
+
+        commands
+            .entity(e)
+            .insert((Mesh3d(quad), MeshMaterial3d(material)));
+    }
+}
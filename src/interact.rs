@@ -0,0 +1,235 @@
+use crate::player;
+use bevy::prelude::*;
+use bevy::render::mesh::PrimitiveTopology;
+use bevy::scene::SceneInstanceReady;
+use leafwing_input_manager::prelude::ActionState;
+use parry3d::math::{Isometry, Point};
+use parry3d::na::{Point3, Vector3};
+use parry3d::query::Ray;
+use parry3d::shape::SharedShape;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<Hovered>();
+    app.init_resource::<PickReach>();
+    app.add_event::<Clicked>();
+    app.add_event::<Held>();
+    app.add_event::<HoverStart>();
+    app.add_event::<HoverEnd>();
+    app.add_observer(attach_scene_pickers);
+    app.add_systems(Update, pick);
+}
+
+/// How far the player can pick things from, in world units.
+#[derive(Resource)]
+pub struct PickReach(pub f32);
+
+impl Default for PickReach {
+    fn default() -> Self {
+        PickReach(50.0)
+    }
+}
+
+/// Something the player can point at and click.
+///
+/// The shape is in the entity's local space, so it follows its `GlobalTransform`
+/// (scale included) without being rebuilt.
+#[derive(Component, Clone)]
+pub struct Interactable {
+    shape: SharedShape,
+    owner: Option<Entity>,
+}
+
+impl Interactable {
+    /// A flat quad in the local XY plane, facing +Z.
+    pub fn quad(size: Vec2) -> Self {
+        let h = size * 0.5;
+        let points = vec![
+            Point3::new(h.x, h.y, 0.0),
+            Point3::new(h.x, -h.y, 0.0),
+            Point3::new(-h.x, -h.y, 0.0),
+            Point3::new(-h.x, h.y, 0.0),
+        ];
+        let indices = vec![[0u32, 1, 3], [2, 3, 1]];
+
+        Interactable {
+            shape: SharedShape::trimesh(points, indices).unwrap(),
+            owner: None,
+        }
+    }
+
+    pub fn cuboid(half_extents: Vec3) -> Self {
+        Interactable {
+            shape: SharedShape::cuboid(half_extents.x, half_extents.y, half_extents.z),
+            owner: None,
+        }
+    }
+
+    /// Build a triangle mesh shape from an indexed triangle list mesh.
+    pub fn from_mesh(mesh: &Mesh) -> Option<Self> {
+        if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+            return None;
+        }
+        let positions = mesh.attribute(Mesh::ATTRIBUTE_POSITION)?.as_float3()?;
+        let indices: Vec<u32> = mesh.indices()?.iter().map(|i| i as u32).collect();
+
+        let points = positions.iter().map(|p| Point3::from(*p)).collect();
+        let tris = indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect();
+
+        Some(Interactable {
+            shape: SharedShape::trimesh(points, tris).ok()?,
+            owner: None,
+        })
+    }
+
+    /// Report hits on this shape as hits on `owner`, e.g. the root of a scene.
+    pub fn with_owner(mut self, owner: Entity) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    /// Cast a world space ray against this shape placed at `transform`.
+    pub fn cast(
+        &self,
+        transform: &GlobalTransform,
+        origin: Vec3,
+        dir: Vec3,
+        max: f32,
+    ) -> Option<(f32, Vec3)> {
+        let affine = transform.affine();
+        if affine.matrix3.determinant().abs() <= f32::EPSILON {
+            return None;
+        }
+        let inv = affine.inverse();
+
+        // The local direction is left unnormalized, so the time of impact stays
+        // a world space distance.
+        let l_origin = inv.transform_point3(origin);
+        let l_dir = inv.transform_vector3(dir);
+        let ray = Ray::new(
+            Point::from(l_origin.to_array()),
+            Vector3::from(l_dir.to_array()),
+        );
+
+        let hit = self
+            .shape
+            .cast_ray_and_get_normal(&Isometry::identity(), &ray, max, true)?;
+        let l_normal = Vec3::new(hit.normal.x, hit.normal.y, hit.normal.z);
+        let normal = inv.matrix3.transpose() * l_normal;
+
+        Some((hit.time_of_impact, normal.normalize_or_zero()))
+    }
+}
+
+/// Makes every mesh in a spawned scene pickable, reporting hits on this entity.
+#[derive(Component, Default)]
+pub struct PickableScene;
+
+/// Runs once per scene instance, when all of its entities have been spawned.
+fn attach_scene_pickers(
+    trigger: Trigger<SceneInstanceReady>,
+    mut commands: Commands,
+    meshes: Res<Assets<Mesh>>,
+    scenes: Query<(), With<PickableScene>>,
+    children: Query<&Children>,
+    parts: Query<&Mesh3d, Without<Interactable>>,
+) {
+    let root = trigger.target();
+    if !scenes.contains(root) {
+        return;
+    }
+    for part in children.iter_descendants(root) {
+        let Ok(mesh) = parts.get(part) else {
+            continue;
+        };
+        let Some(mesh) = meshes.get(mesh.id()) else {
+            continue;
+        };
+        if let Some(shape) = Interactable::from_mesh(mesh) {
+            commands.entity(part).insert(shape.with_owner(root));
+        }
+    }
+}
+
+/// The closest thing under the player's crosshair.
+#[derive(Clone, Copy, Debug)]
+pub struct PickHit {
+    pub entity: Entity,
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+#[derive(Resource, Default)]
+pub struct Hovered(pub Option<PickHit>);
+
+/// The click action was just pressed while pointing at an entity.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct Clicked(pub PickHit);
+
+/// Sent every frame the click action is down while pointing at an entity.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct Held(pub PickHit);
+
+#[derive(Event, Clone, Copy, Debug)]
+pub struct HoverStart(pub Entity);
+
+#[derive(Event, Clone, Copy, Debug)]
+pub struct HoverEnd(pub Entity);
+
+pub fn pick(
+    mut hovered: ResMut<Hovered>,
+    mut clicked: EventWriter<Clicked>,
+    mut held: EventWriter<Held>,
+    mut hover_start: EventWriter<HoverStart>,
+    mut hover_end: EventWriter<HoverEnd>,
+    reach: Res<PickReach>,
+    players: Query<(&Transform, &ActionState<player::PlayerAction>), With<player::Player>>,
+    targets: Query<(Entity, &Interactable, &GlobalTransform)>,
+) {
+    let Ok((p_trans, action)) = players.single() else {
+        return;
+    };
+    let origin = p_trans.translation;
+    let dir = Vec3::from(p_trans.forward());
+
+    let mut nearest: Option<PickHit> = None;
+    for (e, target, g_trans) in &targets {
+        let Some((distance, normal)) = target.cast(g_trans, origin, dir, reach.0) else {
+            continue;
+        };
+        if nearest.is_some_and(|n| n.distance <= distance) {
+            continue;
+        }
+        nearest = Some(PickHit {
+            entity: target.owner.unwrap_or(e),
+            point: origin + dir * distance,
+            normal,
+            distance,
+        });
+    }
+
+    let old = hovered.0.map(|h| h.entity);
+    let new = nearest.map(|h| h.entity);
+    if old != new {
+        if let Some(e) = old {
+            hover_end.write(HoverEnd(e));
+        }
+        if let Some(e) = new {
+            hover_start.write(HoverStart(e));
+        }
+    }
+    hovered.0 = nearest;
+
+    let Some(hit) = nearest else {
+        return;
+    };
+    if action.just_pressed(&player::PlayerAction::Click) {
+        clicked.write(Clicked(hit));
+    }
+    if action.pressed(&player::PlayerAction::Click) {
+        held.write(Held(hit));
+    }
+}
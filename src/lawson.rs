@@ -13,12 +13,18 @@ struct Lawson {
 }
 
 fn setup(mut commands: Commands, assets: Res<AssetServer>) {
-    commands.spawn((
-        SceneRoot(assets.load(GltfAssetLabel::Scene(0).from_asset("lawson.glb"))),
-        Lawson { y: 0.7 },
-        Transform::from_scale(Vec3::splat(1.0 / 5.0)).with_translation(Vec3::new(-5.0, 0.7, 2.0)),
-        crate::sinphase::SinPhase::new(0.25),
-    ));
+    let npc = commands
+        .spawn((
+            SceneRoot(assets.load(GltfAssetLabel::Scene(0).from_asset("lawson.glb"))),
+            Lawson { y: 0.7 },
+            crate::interact::PickableScene,
+            crate::shadow::BlobShadow::new(0.3),
+            Transform::from_scale(Vec3::splat(1.0 / 5.0))
+                .with_translation(Vec3::new(-5.0, 0.7, 2.0)),
+            crate::sinphase::SinPhase::new(0.25),
+        ))
+        .id();
+    commands.spawn(crate::textboard::name_tag("Lawson", npc, 0.5));
 }
 
 fn update(
@@ -0,0 +1,345 @@
+use crate::GameSettings;
+use crate::cube::{Cube, CubeMesh, CubeTiles};
+use crate::flat::{FlatMaterial, MaterialCache, MaterialPair, ShadedMaterial};
+use bevy::input::common_conditions::input_just_pressed;
+use bevy::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Pass `--level <path>` to pick the level file. Without it `level.ron` in the
+/// working directory is used, and loaded at startup if it exists.
+const LEVEL_FLAG: &str = "--level";
+const DEFAULT_LEVEL: &str = "level.ron";
+
+pub fn plugin(app: &mut App) {
+    let arg = std::env::args()
+        .skip_while(|a| a != LEVEL_FLAG)
+        .nth(1)
+        .map(PathBuf::from);
+    app.insert_resource(LevelFile {
+        explicit: arg.is_some(),
+        path: arg.unwrap_or_else(|| DEFAULT_LEVEL.into()),
+    });
+    app.add_systems(
+        PostStartup,
+        load_level.run_if(|file: Res<LevelFile>| file.explicit || file.path.exists()),
+    );
+    app.add_systems(
+        Update,
+        (
+            save_level.run_if(input_just_pressed(KeyCode::F5)),
+            load_level.run_if(input_just_pressed(KeyCode::F9)),
+        ),
+    );
+}
+
+/// Where F5 saves the cubes to and F9 loads them from.
+#[derive(Resource)]
+pub struct LevelFile {
+    pub path: PathBuf,
+    explicit: bool,
+}
+
+/// Every cube in the world, as stored in a level file.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct Level {
+    pub cubes: Vec<LevelCube>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct LevelCube {
+    pub translation: [f32; 3],
+    #[serde(default = "no_rotation")]
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+    /// sRGB with alpha.
+    pub color: [f32; 4],
+    #[serde(default, skip_serializing_if = "LevelAlpha::is_opaque")]
+    pub alpha_mode: LevelAlpha,
+    /// Asset path of the texture.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texture: Option<String>,
+    /// Atlas tiles for +X, -X, +Y, -Y, +Z, -Z, see [`CubeTiles`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tiles: Option<[u16; 6]>,
+}
+
+fn no_rotation() -> [f32; 4] {
+    Quat::IDENTITY.to_array()
+}
+
+/// [`AlphaMode`] as stored in a level file.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum LevelAlpha {
+    #[default]
+    Opaque,
+    Mask(f32),
+    Blend,
+    Premultiplied,
+    AlphaToCoverage,
+    Add,
+    Multiply,
+}
+
+impl LevelAlpha {
+    fn is_opaque(&self) -> bool {
+        *self == LevelAlpha::Opaque
+    }
+}
+
+impl From<AlphaMode> for LevelAlpha {
+    fn from(mode: AlphaMode) -> Self {
+        match mode {
+            AlphaMode::Opaque => LevelAlpha::Opaque,
+            AlphaMode::Mask(cutoff) => LevelAlpha::Mask(cutoff),
+            AlphaMode::Blend => LevelAlpha::Blend,
+            AlphaMode::Premultiplied => LevelAlpha::Premultiplied,
+            AlphaMode::AlphaToCoverage => LevelAlpha::AlphaToCoverage,
+            AlphaMode::Add => LevelAlpha::Add,
+            AlphaMode::Multiply => LevelAlpha::Multiply,
+        }
+    }
+}
+
+impl From<LevelAlpha> for AlphaMode {
+    fn from(mode: LevelAlpha) -> Self {
+        match mode {
+            LevelAlpha::Opaque => AlphaMode::Opaque,
+            LevelAlpha::Mask(cutoff) => AlphaMode::Mask(cutoff),
+            LevelAlpha::Blend => AlphaMode::Blend,
+            LevelAlpha::Premultiplied => AlphaMode::Premultiplied,
+            LevelAlpha::AlphaToCoverage => AlphaMode::AlphaToCoverage,
+            LevelAlpha::Add => AlphaMode::Add,
+            LevelAlpha::Multiply => AlphaMode::Multiply,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, ron::error::SpannedError),
+    /// The cube at this index has a rotation that can't be normalized.
+    Rotation(PathBuf, usize),
+    Serialize(ron::Error),
+    Write(PathBuf, std::io::Error),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelError::Read(path, e) => write!(f, "couldn't read level {}: {e}", path.display()),
+            LevelError::Parse(path, e) => write!(f, "level {} is invalid at {e}", path.display()),
+            LevelError::Rotation(path, i) => {
+                write!(
+                    f,
+                    "cube {i} in level {} has no valid rotation",
+                    path.display()
+                )
+            }
+            LevelError::Serialize(e) => write!(f, "couldn't serialize level: {e}"),
+            LevelError::Write(path, e) => {
+                write!(f, "couldn't write level {}: {e}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+impl Level {
+    pub fn read(path: &Path) -> Result<Self, LevelError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| LevelError::Read(path.to_owned(), e))?;
+        let mut level: Level =
+            ron::from_str(&text).map_err(|e| LevelError::Parse(path.to_owned(), e))?;
+        level.normalize(path)?;
+        Ok(level)
+    }
+
+    /// Normalize the cube rotations, failing on any that are about zero.
+    fn normalize(&mut self, path: &Path) -> Result<(), LevelError> {
+        for (i, cube) in self.cubes.iter_mut().enumerate() {
+            let rotation = Quat::from_array(cube.rotation);
+            if !rotation.is_finite() || rotation.length_squared() < 1e-6 {
+                return Err(LevelError::Rotation(path.to_owned(), i));
+            }
+            cube.rotation = rotation.normalize().to_array();
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), LevelError> {
+        let text = ron::ser::to_string_pretty(self, PrettyConfig::default())
+            .map_err(LevelError::Serialize)?;
+        std::fs::write(path, text).map_err(|e| LevelError::Write(path.to_owned(), e))
+    }
+}
+
+fn save_level(
+    file: Res<LevelFile>,
+    f_mats: Res<Assets<FlatMaterial>>,
+    s_mats: Res<Assets<ShadedMaterial>>,
+    cubes: Query<(
+        &Transform,
+        &Cube,
+        Option<&MaterialPair>,
+        Option<&MeshMaterial3d<FlatMaterial>>,
+        Option<&MeshMaterial3d<ShadedMaterial>>,
+    )>,
+) {
+    let mut level = Level::default();
+    for (trans, cube, pair, f_mat, s_mat) in &cubes {
+        // Either half of the pair describes the same look.
+        let f_mat = pair.map(|p| p.flat.id()).or(f_mat.map(|m| m.id()));
+        let s_mat = pair.map(|p| p.standard.id()).or(s_mat.map(|m| m.id()));
+        let (color, texture, alpha_mode) = if let Some(mat) = f_mat.and_then(|m| f_mats.get(m)) {
+            (Color::from(mat.color), mat.texture.as_ref(), mat.alpha_mode)
+        } else if let Some(mat) = s_mat.and_then(|m| s_mats.get(m)) {
+            (
+                mat.base.base_color,
+                mat.base.base_color_texture.as_ref(),
+                mat.base.alpha_mode,
+            )
+        } else {
+            (Color::WHITE, None, AlphaMode::Opaque)
+        };
+
+        let texture = texture.and_then(|t| {
+            let path = t.path().map(|p| p.to_string());
+            if path.is_none() {
+                warn!("Cube texture {t:?} has no asset path and won't be saved");
+            }
+            path
+        });
+
+        level.cubes.push(LevelCube {
+            translation: trans.translation.to_array(),
+            rotation: trans.rotation.to_array(),
+            scale: trans.scale.to_array(),
+            color: color.to_srgba().to_f32_array(),
+            alpha_mode: alpha_mode.into(),
+            texture,
+            tiles: cube.tiles.map(|t| t.0),
+        });
+    }
+
+    match level.write(&file.path) {
+        Ok(()) => info!(
+            "Saved {} cubes to {}",
+            level.cubes.len(),
+            file.path.display()
+        ),
+        Err(e) => error!("{e}"),
+    }
+}
+
+/// Replace every cube with the ones in the level file. The whole file is read
+/// and checked first, so on error the current cubes are left alone.
+fn load_level(
+    mut commands: Commands,
+    file: Res<LevelFile>,
+    assets: Res<AssetServer>,
+    mut cache: ResMut<MaterialCache>,
+    mut f_mats: ResMut<Assets<FlatMaterial>>,
+    mut s_mats: ResMut<Assets<ShadedMaterial>>,
+    cube_mesh: Res<CubeMesh>,
+    g_set: Res<GameSettings>,
+    cubes: Query<Entity, With<Cube>>,
+) {
+    let level = match Level::read(&file.path) {
+        Ok(level) => level,
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    };
+
+    for e in &cubes {
+        commands.entity(e).despawn();
+    }
+
+    for cube in &level.cubes {
+        let [r, g, b, a] = cube.color;
+        let pair = cache.get(
+            &mut f_mats,
+            &mut s_mats,
+            Color::srgba(r, g, b, a),
+            cube.texture.as_ref().map(|p| assets.load(p)),
+            cube.alpha_mode.into(),
+        );
+        pair.apply(
+            &mut commands.spawn((
+                Cube {
+                    tiles: cube.tiles.map(CubeTiles),
+                },
+                Mesh3d(cube_mesh.0.clone()),
+                Transform {
+                    translation: Vec3::from_array(cube.translation),
+                    rotation: Quat::from_array(cube.rotation),
+                    scale: Vec3::from_array(cube.scale),
+                },
+            )),
+            g_set.contains(GameSettings::FLAT),
+        );
+    }
+
+    info!(
+        "Loaded {} cubes from {}",
+        level.cubes.len(),
+        file.path.display()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let level = Level {
+            cubes: vec![
+                LevelCube {
+                    translation: [0.25, 0.25, -0.75],
+                    rotation: Quat::IDENTITY.to_array(),
+                    scale: [0.5; 3],
+                    color: [1.0, 0.0, 0.0, 1.0],
+                    alpha_mode: LevelAlpha::Opaque,
+                    texture: Some("cube_tex.png".into()),
+                    tiles: None,
+                },
+                LevelCube {
+                    translation: [1.0, 2.0, 3.0],
+                    rotation: Quat::from_rotation_y(1.0).to_array(),
+                    scale: [1.0, 2.0, 1.0],
+                    color: [0.5, 0.5, 1.0, 0.5],
+                    alpha_mode: LevelAlpha::Mask(0.5),
+                    texture: None,
+                    tiles: Some([0, 1, 2, 3, 0, 1]),
+                },
+            ],
+        };
+        let text = ron::ser::to_string_pretty(&level, PrettyConfig::default()).unwrap();
+        let read: Level = ron::from_str(&text).unwrap();
+        assert_eq!(read, level);
+    }
+
+    #[test]
+    fn rotations() {
+        let mut level: Level = ron::from_str(
+            "(cubes: [(translation: (0, 0, 0), rotation: (0, 0, 0, 2), scale: (1, 1, 1), color: (1, 1, 1, 1))])",
+        )
+        .unwrap();
+        level.normalize(Path::new("test.ron")).unwrap();
+        assert_eq!(level.cubes[0].rotation, Quat::IDENTITY.to_array());
+        assert_eq!(level.cubes[0].alpha_mode, LevelAlpha::Opaque);
+
+        level.cubes[0].rotation = [0.0; 4];
+        assert!(matches!(
+            level.normalize(Path::new("test.ron")),
+            Err(LevelError::Rotation(_, 0))
+        ));
+    }
+}
@@ -1,17 +1,28 @@
+mod bench;
 mod billboard;
+mod chunk;
 mod cube;
 mod display;
 mod flat;
 mod grid;
+mod instancing;
+mod interact;
 mod lawson;
+mod level;
+mod particles;
 mod physic_objects;
 mod player;
+mod post;
+mod rng;
+mod shadow;
 mod sinphase;
 mod smile;
+mod sprite;
+mod textboard;
 mod ui;
 mod wyatt;
 
-use crate::flat::{DynamicMaterial, FlatMaterial};
+use crate::flat::{DynamicMaterial, FlatMaterial, ShadedMaterial, shaded};
 use bevy::color::palettes::css::WHITE;
 use bevy::input::common_conditions::input_just_pressed;
 use bevy::prelude::*;
@@ -23,10 +34,14 @@ use bevy_embedded_assets::{EmbeddedAssetPlugin, PluginMode};
 use bevy_fix_cursor_unlock_web::prelude::*;
 use bitflags::bitflags;
 
+/// Size of the render texture in game pixels, kept on `display::Resolution`.
 #[derive(Resource)]
 struct GameSize(Extent3d);
 
-#[derive(Resource)]
+/// Render settings. Flip them at runtime (F2 flat, F3 quantize) and the
+/// materials, display quad and ambient light follow. Quantization is one pass
+/// of `post::PostChain`.
+#[derive(Resource, Clone, Copy)]
 struct GameSettings(u32);
 
 bitflags! {
@@ -81,12 +96,21 @@ fn main() {
             ui::plugin,
             flat::plugin,
             smile::plugin,
+            sprite::plugin,
+        ))
+        .add_plugins((
+            rng::plugin,
+            interact::plugin,
+            textboard::plugin,
+            instancing::plugin,
+            bench::plugin,
+            particles::plugin,
+            chunk::plugin,
+            level::plugin,
+            shadow::plugin,
+            post::plugin,
         ))
-        .insert_resource(GameSize(Extent3d {
-            width: 320,
-            height: 240,
-            depth_or_array_layers: 1,
-        }))
+        .insert_resource(display::RESOLUTIONS[0])
         // .insert_resource(AmbientLight::NONE)
         .init_resource::<GameSettings>()
         .add_systems(Startup, setup)
@@ -96,40 +120,53 @@ fn main() {
                 (toggle_grab_cursor).run_if(input_just_pressed(KeyCode::Tab)),
                 fullscreen.run_if(input_just_pressed(KeyCode::F11)),
                 quit_handler.run_if(input_just_pressed(KeyCode::Escape)),
+                toggle_setting(GameSettings::FLAT).run_if(input_just_pressed(KeyCode::F2)),
+                toggle_setting(GameSettings::COLOR_QUANTIZE)
+                    .run_if(input_just_pressed(KeyCode::F3)),
+                set_ambient_light.run_if(resource_changed::<GameSettings>),
             ),
         )
         .run();
 }
 
 fn setup(
-    game_settings: Res<GameSettings>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut materials: ResMut<Assets<ShadedMaterial>>,
 ) {
     // --- GROUND SETUP ---
 
     commands.spawn((
         Mesh3d(meshes.add(grid::gen_mesh(10))),
-        MeshMaterial3d(materials.add(StandardMaterial {
+        MeshMaterial3d(materials.add(shaded(StandardMaterial {
             base_color: Color::WHITE.into(),
             alpha_mode: AlphaMode::Opaque,
             unlit: true,
             ..default()
-        })),
+        }))),
         DynamicMaterial,
         Transform::from_scale(Vec3::splat(10.0)),
     ));
+}
 
+fn set_ambient_light(mut commands: Commands, game_settings: Res<GameSettings>) {
     if game_settings.contains(GameSettings::FLAT) {
         commands.insert_resource(AmbientLight {
             color: WHITE.into(),
             brightness: 4000.0,
             ..default()
         });
+    } else {
+        // What the PBR plugin starts with.
+        commands.insert_resource(AmbientLight::default());
     }
 }
 
+/// A system flipping `flag`, to bind settings to keys.
+fn toggle_setting(flag: GameSettings) -> impl FnMut(ResMut<GameSettings>) {
+    move |mut game_settings| game_settings.toggle(flag)
+}
+
 fn toggle_grab_cursor(mut window_query: Query<&mut Window, With<PrimaryWindow>>) {
     let mut window = window_query.single_mut().unwrap();
     window.cursor_options.grab_mode = CursorGrabMode::Locked;
@@ -0,0 +1,252 @@
+use crate::GameSettings;
+use crate::billboard::Billboard;
+use crate::flat::{DynamicMaterial, FlatMaterial, MaterialCache, MaterialPair, ShadedMaterial};
+use crate::rng::GameRng;
+use crate::sinphase::SinPhase;
+use bevy::prelude::*;
+use rand::Rng;
+use rand::rngs::StdRng;
+use std::f32::consts::TAU;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<ParticleQuad>();
+    app.init_resource::<ParticleRng>();
+    app.add_systems(Update, (emit, simulate).chain());
+}
+
+/// Spawns short lived billboard particles.
+///
+/// With a [`SinPhase`] on the same entity, the spawn rate pulses with it.
+#[derive(Component, Clone, Debug)]
+#[require(Transform, EmitterClock)]
+pub struct ParticleEmitter {
+    /// Particles per second.
+    pub rate: f32,
+    /// Seconds each particle lives.
+    pub lifetime: f32,
+    /// Launch direction in world space. The emitter's rotation is left out, as
+    /// on a billboard it only follows the camera and the spin.
+    pub direction: Vec3,
+    /// Half angle of the launch cone, in radians.
+    pub cone: f32,
+    /// Launch speed range.
+    pub speed: (f32, f32),
+    pub gravity: Vec3,
+    /// World size at birth and death.
+    pub size: (f32, f32),
+    /// Color at birth and death.
+    pub color: (Color, Color),
+    pub texture: Option<Handle<Image>>,
+    pub alpha_mode: AlphaMode,
+    pub enabled: bool,
+    /// Particles to spawn at once on the next update, on top of the rate.
+    pub burst: u32,
+}
+
+/// Fractional particles carried over between frames.
+#[derive(Component, Default)]
+struct EmitterClock(f32);
+
+impl Default for ParticleEmitter {
+    fn default() -> Self {
+        ParticleEmitter {
+            rate: 10.0,
+            lifetime: 1.0,
+            direction: Vec3::Y,
+            cone: 0.3,
+            speed: (1.0, 2.0),
+            gravity: Vec3::new(0.0, -9.8, 0.0),
+            size: (0.1, 0.0),
+            color: (Color::WHITE, Color::WHITE.with_alpha(0.0)),
+            texture: None,
+            alpha_mode: AlphaMode::Blend,
+            enabled: true,
+            burst: 0,
+        }
+    }
+}
+
+/// Colors a particle goes through from birth to death. Each step is a
+/// material pair shared through the [`MaterialCache`], so particles of one
+/// emitter batch together instead of each owning a material.
+const FADE_STEPS: u32 = 16;
+
+#[derive(Component)]
+pub struct Particle {
+    velocity: Vec3,
+    gravity: Vec3,
+    age: f32,
+    lifetime: f32,
+    size: (f32, f32),
+    color: (Color, Color),
+    texture: Option<Handle<Image>>,
+    alpha_mode: AlphaMode,
+    /// The fade step currently shown.
+    step: u32,
+}
+
+impl Particle {
+    fn step_color(&self, step: u32) -> Color {
+        let t = step as f32 / (FADE_STEPS - 1) as f32;
+        self.color.0.mix(&self.color.1, t)
+    }
+
+    fn material(
+        &self,
+        cache: &mut MaterialCache,
+        f_mats: &mut Assets<FlatMaterial>,
+        s_mats: &mut Assets<ShadedMaterial>,
+    ) -> MaterialPair {
+        cache.get(
+            f_mats,
+            s_mats,
+            self.step_color(self.step),
+            self.texture.clone(),
+            self.alpha_mode,
+        )
+    }
+}
+
+/// One quad mesh shared by every particle.
+#[derive(Resource)]
+struct ParticleQuad(Handle<Mesh>);
+
+impl FromWorld for ParticleQuad {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        ParticleQuad(meshes.add(Rectangle::new(1.0, 1.0)))
+    }
+}
+
+/// Particles draw from their own stream so the number spawned each frame
+/// doesn't shift the gameplay randomness.
+#[derive(Resource)]
+struct ParticleRng(StdRng);
+
+impl FromWorld for ParticleRng {
+    fn from_world(world: &mut World) -> Self {
+        ParticleRng(world.resource::<GameRng>().fork(PARTICLE_STREAM))
+    }
+}
+
+const PARTICLE_STREAM: u64 = 1;
+
+/// A random unit vector within `cone` radians of `dir`.
+fn cone_dir(dir: Vec3, cone: f32, rng: &mut impl Rng) -> Vec3 {
+    let theta = rng.r#gen::<f32>() * cone;
+    let phi = rng.r#gen::<f32>() * TAU;
+    let local = Vec3::new(
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        theta.sin() * phi.sin(),
+    );
+    Quat::from_rotation_arc(Vec3::Y, dir.normalize_or(Vec3::Y)) * local
+}
+
+fn emit(
+    mut commands: Commands,
+    mut emitters: Query<(
+        &mut ParticleEmitter,
+        &mut EmitterClock,
+        &GlobalTransform,
+        Option<&SinPhase>,
+    )>,
+    mut f_mats: ResMut<Assets<FlatMaterial>>,
+    mut s_mats: ResMut<Assets<ShadedMaterial>>,
+    mut cache: ResMut<MaterialCache>,
+    quad: Res<ParticleQuad>,
+    mut rng: ResMut<ParticleRng>,
+    g_set: Res<GameSettings>,
+    time: Res<Time>,
+) {
+    for (mut emitter, mut pending, g_trans, phase) in &mut emitters {
+        if emitter.enabled {
+            let pulse = phase.map_or(1.0, |p| (p.get_phase() + 1.0) * 0.5);
+            pending.0 += emitter.rate * pulse * time.delta_secs();
+        }
+        if emitter.burst > 0 {
+            pending.0 += emitter.burst as f32;
+            emitter.burst = 0;
+        }
+
+        let origin = g_trans.translation();
+
+        while pending.0 >= 1.0 {
+            pending.0 -= 1.0;
+
+            let (lo, hi) = emitter.speed;
+            let speed = lo + (hi - lo) * rng.0.r#gen::<f32>();
+
+            let particle = Particle {
+                velocity: cone_dir(emitter.direction, emitter.cone, &mut rng.0) * speed,
+                gravity: emitter.gravity,
+                age: 0.0,
+                lifetime: emitter.lifetime,
+                size: emitter.size,
+                color: emitter.color,
+                texture: emitter.texture.clone(),
+                alpha_mode: emitter.alpha_mode,
+                step: 0,
+            };
+            let pair = particle.material(&mut cache, &mut f_mats, &mut s_mats);
+
+            // Particles spawn all the time, so pick the material for the current mode.
+            pair.apply(
+                &mut commands.spawn((
+                    particle,
+                    Billboard::default(),
+                    DynamicMaterial,
+                    Mesh3d(quad.0.clone()),
+                    Transform::from_translation(origin).with_scale(Vec3::splat(emitter.size.0)),
+                )),
+                g_set.contains(GameSettings::FLAT),
+            );
+        }
+    }
+}
+
+fn simulate(
+    mut commands: Commands,
+    mut particles: Query<(
+        Entity,
+        &mut Particle,
+        &mut Transform,
+        &mut MaterialPair,
+        Option<&mut MeshMaterial3d<FlatMaterial>>,
+        Option<&mut MeshMaterial3d<ShadedMaterial>>,
+    )>,
+    mut f_mats: ResMut<Assets<FlatMaterial>>,
+    mut s_mats: ResMut<Assets<ShadedMaterial>>,
+    mut cache: ResMut<MaterialCache>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    for (e, mut particle, mut trans, mut pair, f_mat, s_mat) in &mut particles {
+        particle.age += dt;
+        if particle.age >= particle.lifetime {
+            commands.entity(e).despawn();
+            continue;
+        }
+
+        let gravity = particle.gravity;
+        particle.velocity += gravity * dt;
+        trans.translation += particle.velocity * dt;
+
+        let t = particle.age / particle.lifetime;
+        let (from, to) = particle.size;
+        trans.scale = Vec3::splat(from + (to - from) * t);
+
+        let step = (t * (FADE_STEPS - 1) as f32).round() as u32;
+        if step == particle.step {
+            continue;
+        }
+        particle.step = step;
+        *pair = particle.material(&mut cache, &mut f_mats, &mut s_mats);
+        if let Some(mut mat) = f_mat {
+            mat.0 = pair.flat.clone();
+        }
+        if let Some(mut mat) = s_mat {
+            mat.0 = pair.standard.clone();
+        }
+    }
+}
@@ -1,5 +1,8 @@
-use crate::MainCamera;
+use crate::chunk::CubeWorld;
+use crate::cube::{Cube, CubeGrid};
 use crate::display::RenderTex;
+use crate::{GameSize, MainCamera};
+use bevy::math::bounding::Aabb3d;
 use bevy::prelude::*;
 use bevy::render::camera::RenderTarget;
 use bevy::render::view::RenderLayers;
@@ -23,6 +26,8 @@ pub enum PlayerAction {
     Look,
     Click,
     Sprint,
+    Place,
+    Remove,
 }
 
 impl PlayerAction {
@@ -41,12 +46,16 @@ impl PlayerAction {
         );
         input_map.insert(Self::Click, GamepadButton::RightTrigger2);
         input_map.insert(Self::Sprint, GamepadButton::LeftTrigger2);
+        input_map.insert(Self::Place, GamepadButton::RightTrigger);
+        input_map.insert(Self::Remove, GamepadButton::LeftTrigger);
 
         // Default kbm input bindings
         input_map.insert_dual_axis(Self::Move, VirtualDPad::wasd());
         input_map.insert_dual_axis(Self::Look, MouseMove::default());
         input_map.insert(Self::Click, MouseButton::Left);
         input_map.insert(Self::Sprint, KeyCode::Space);
+        input_map.insert(Self::Place, MouseButton::Right);
+        input_map.insert(Self::Remove, KeyCode::KeyQ);
 
         input_map
     }
@@ -80,11 +89,23 @@ impl Default for Player {
     }
 }
 
+impl Player {
+    /// The space taken by a player whose eyes are at `eye`, down to the feet.
+    pub fn bounds(&self, eye: Vec3) -> Aabb3d {
+        let radius = self.fat + self.step_dist;
+        let half_height = self.height * 0.5;
+        Aabb3d::new(
+            eye - Vec3::Y * half_height,
+            Vec3::new(radius, half_height, radius),
+        )
+    }
+}
+
 #[derive(Component)]
 #[component(storage = "SparseSet")]
 struct Moving;
 
-fn setup(mut commands: Commands, render_tex: Res<RenderTex>) {
+fn setup(mut commands: Commands, render_tex: Res<RenderTex>, game_size: Res<GameSize>) {
     commands.spawn((
         Camera3d::default(),
         Camera {
@@ -95,7 +116,7 @@ fn setup(mut commands: Commands, render_tex: Res<RenderTex>) {
         },
         Projection::from(PerspectiveProjection {
             fov: FRAC_PI_4,
-            aspect_ratio: 4.0 / 3.0,
+            aspect_ratio: game_size.0.width as f32 / game_size.0.height as f32,
             near: 0.0001,
             ..default()
         }),
@@ -153,8 +174,10 @@ fn movement_input(
 }
 
 fn physics(
-    mut player_query: Query<(&mut Transform, &Player), Without<crate::cube::Cube>>,
-    cubes: Query<&Transform, With<crate::cube::Cube>>,
+    mut player_query: Query<(&mut Transform, &Player), Without<Cube>>,
+    cubes: Query<&Transform, With<Cube>>,
+    world: Res<CubeWorld>,
+    grid: Res<CubeGrid>,
 ) {
     let (mut trans, player) = player_query.single_mut().unwrap();
 
@@ -163,7 +186,8 @@ fn physics(
         trans.translation += Vec3::new(slide.x, 0.0, slide.y);
 
         // Push-Out
-        for cube in cubes {
+        let near = world.near(&grid, trans.translation.xz(), player.fat);
+        for cube in cubes.iter_many(near) {
             let boid = parry2d::shape::Cuboid::new(
                 parry2d::na::Vector2::new(cube.scale.x, cube.scale.z) * 0.5,
             );
@@ -185,20 +209,23 @@ fn physics(
     };
 }
 
-fn stepping(
-    mut player_query: Query<(&mut Transform, &Player), Without<crate::cube::Cube>>,
-    cubes: Query<&Transform, With<crate::cube::Cube>>,
-) {
-    let (mut trans, player) = player_query.single_mut().unwrap();
+/// Top of the highest cube touching a circle of `radius` around `point` on the
+/// XZ plane, ignoring cubes whose top is above `ceiling`.
+pub fn highest_surface<'a>(
+    point: Vec2,
+    radius: f32,
+    ceiling: f32,
+    cubes: impl IntoIterator<Item = &'a Transform>,
+) -> Option<f32> {
     let mut highest_point: f32 = f32::NEG_INFINITY;
-    let p_shape = parry2d::shape::Ball::new(player.fat + player.step_dist);
-    for cube in cubes.iter() {
+    let p_shape = parry2d::shape::Ball::new(radius);
+    for cube in cubes {
         let boid = parry2d::shape::Cuboid::new(
             parry2d::na::Vector2::new(cube.scale.x, cube.scale.z) * 0.5,
         );
 
         if !parry2d::query::intersection_test(
-            &parry2d::math::Translation::new(trans.translation.x, trans.translation.z).into(),
+            &parry2d::math::Translation::new(point.x, point.y).into(),
             &p_shape,
             &parry2d::math::Translation::new(cube.translation.x, cube.translation.z).into(),
             &boid,
@@ -209,12 +236,31 @@ fn stepping(
         }
 
         let height = cube.translation.y + cube.scale.y * 0.5;
-        if height > highest_point {
+        if height > highest_point && height <= ceiling {
             highest_point = height;
         }
     }
 
-    if highest_point > f32::NEG_INFINITY {
+    (highest_point > f32::NEG_INFINITY).then_some(highest_point)
+}
+
+fn stepping(
+    mut player_query: Query<(&mut Transform, &Player), Without<Cube>>,
+    cubes: Query<&Transform, With<Cube>>,
+    world: Res<CubeWorld>,
+    grid: Res<CubeGrid>,
+) {
+    let (mut trans, player) = player_query.single_mut().unwrap();
+    let radius = player.fat + player.step_dist;
+    let near = world.near(&grid, trans.translation.xz(), radius);
+    let highest_point = highest_surface(
+        trans.translation.xz(),
+        radius,
+        f32::INFINITY,
+        cubes.iter_many(near),
+    );
+
+    if let Some(highest_point) = highest_point {
         trans.translation.y = highest_point + player.height;
     } else {
         trans.translation.y = player.height;
@@ -0,0 +1,383 @@
+use crate::GameSettings;
+use bevy::asset::RenderAssetUsages;
+use bevy::image::ImageSampler;
+use bevy::input::common_conditions::input_just_pressed;
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{
+    AsBindGroup, AsBindGroupShaderType, Extent3d, ShaderRef, ShaderType, TextureDimension,
+    TextureFormat,
+};
+use bevy::render::texture::GpuImage;
+use bevy::sprite::{Material2d, Material2dPlugin};
+
+pub fn plugin(app: &mut App) {
+    app.add_plugins(Material2dPlugin::<PostMaterial>::default());
+    app.init_resource::<PostChain>();
+    app.init_resource::<PalettePresets>();
+    app.add_systems(
+        Update,
+        (
+            follow_quantize_flag.run_if(resource_changed::<GameSettings>),
+            cycle_palette.run_if(input_just_pressed(KeyCode::F4)),
+            cycle_dither.run_if(input_just_pressed(KeyCode::F6)),
+            toggle_crt.run_if(input_just_pressed(KeyCode::F7)),
+            sync_chain.run_if(resource_changed::<PostChain>),
+        )
+            .chain(),
+    );
+}
+
+/// Full screen passes run over the game picture on its way to the window, in
+/// order. Each kind of pass is used once; later duplicates are ignored.
+#[derive(Resource, Clone, Debug)]
+pub struct PostChain(pub Vec<PostStage>);
+
+#[derive(Clone, Debug)]
+pub struct PostStage {
+    pub pass: PostPass,
+    pub enabled: bool,
+}
+
+impl PostStage {
+    pub fn new(pass: PostPass, enabled: bool) -> Self {
+        PostStage { pass, enabled }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum PostPass {
+    /// Follows `GameSettings::COLOR_QUANTIZE`.
+    Quantize(Quantizer),
+    Crt {
+        /// How dark the gaps between game pixel rows get, from 0 to 1.
+        scanlines: f32,
+        /// How much the picture bulges towards the corners.
+        curvature: f32,
+    },
+    ChromaticAberration {
+        /// Red and blue split at the edges, in game pixels.
+        amount: f32,
+    },
+    Vignette {
+        strength: f32,
+        /// Distance from the center, where 1 is the middle of an edge, at
+        /// which darkening starts.
+        radius: f32,
+    },
+    Fade {
+        color: Color,
+        /// 0 shows the game, 1 only `color`.
+        amount: f32,
+    },
+}
+
+impl Default for PostChain {
+    fn default() -> Self {
+        PostChain(vec![
+            PostStage::new(PostPass::Quantize(Quantizer::default()), true),
+            PostStage::new(PostPass::ChromaticAberration { amount: 0.5 }, false),
+            PostStage::new(
+                PostPass::Crt {
+                    scanlines: 0.35,
+                    curvature: 0.04,
+                },
+                false,
+            ),
+            PostStage::new(
+                PostPass::Vignette {
+                    strength: 0.5,
+                    radius: 0.6,
+                },
+                false,
+            ),
+            PostStage::new(
+                PostPass::Fade {
+                    color: Color::BLACK,
+                    amount: 0.0,
+                },
+                false,
+            ),
+        ])
+    }
+}
+
+impl PostChain {
+    pub fn any_enabled(&self) -> bool {
+        self.0.iter().any(|s| s.enabled)
+    }
+
+    pub fn quantizer_mut(&mut self) -> Option<&mut Quantizer> {
+        self.0.iter_mut().find_map(|s| match &mut s.pass {
+            PostPass::Quantize(q) => Some(q),
+            _ => None,
+        })
+    }
+
+    fn stage_mut(&mut self, pick: impl Fn(&PostPass) -> bool) -> Option<&mut PostStage> {
+        self.0.iter_mut().find(|s| pick(&s.pass))
+    }
+}
+
+/// Colors snapped to a few levels per channel or to a palette.
+#[derive(Clone, Debug)]
+pub struct Quantizer {
+    /// Levels per color channel, used without a palette.
+    pub levels: u32,
+    /// A row of colors (see [`palette_image`]); every pixel snaps to the
+    /// nearest one.
+    pub palette: Option<Handle<Image>>,
+    pub dither: Dither,
+    /// 1.0 spreads the dither over one quantization step.
+    pub dither_strength: f32,
+}
+
+impl Default for Quantizer {
+    fn default() -> Self {
+        Quantizer {
+            levels: 8,
+            palette: None,
+            dither: Dither::Off,
+            dither_strength: 1.0,
+        }
+    }
+}
+
+/// Ordered dithering applied before quantizing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    #[default]
+    Off = 0,
+    Bayer4 = 1,
+    Bayer8 = 2,
+    /// Interleaved gradient noise, a cheap blue-noise-like pattern.
+    Noise = 3,
+}
+
+/// Palettes F4 cycles through, after none.
+#[derive(Resource)]
+struct PalettePresets(Vec<Handle<Image>>);
+
+impl FromWorld for PalettePresets {
+    fn from_world(world: &mut World) -> Self {
+        let mut images = world.resource_mut::<Assets<Image>>();
+        PalettePresets(vec![
+            images.add(palette_image(&PICO8)),
+            images.add(palette_image(&GAMEBOY)),
+        ])
+    }
+}
+
+/// The PICO-8 palette.
+pub const PICO8: [Srgba; 16] = [
+    Srgba::rgb(0.0, 0.0, 0.0),
+    Srgba::rgb(29.0 / 255.0, 43.0 / 255.0, 83.0 / 255.0),
+    Srgba::rgb(126.0 / 255.0, 37.0 / 255.0, 83.0 / 255.0),
+    Srgba::rgb(0.0, 135.0 / 255.0, 81.0 / 255.0),
+    Srgba::rgb(171.0 / 255.0, 82.0 / 255.0, 54.0 / 255.0),
+    Srgba::rgb(95.0 / 255.0, 87.0 / 255.0, 79.0 / 255.0),
+    Srgba::rgb(194.0 / 255.0, 195.0 / 255.0, 199.0 / 255.0),
+    Srgba::rgb(1.0, 241.0 / 255.0, 232.0 / 255.0),
+    Srgba::rgb(1.0, 0.0, 77.0 / 255.0),
+    Srgba::rgb(1.0, 163.0 / 255.0, 0.0),
+    Srgba::rgb(1.0, 236.0 / 255.0, 39.0 / 255.0),
+    Srgba::rgb(0.0, 228.0 / 255.0, 54.0 / 255.0),
+    Srgba::rgb(41.0 / 255.0, 173.0 / 255.0, 1.0),
+    Srgba::rgb(131.0 / 255.0, 118.0 / 255.0, 156.0 / 255.0),
+    Srgba::rgb(1.0, 119.0 / 255.0, 168.0 / 255.0),
+    Srgba::rgb(1.0, 204.0 / 255.0, 170.0 / 255.0),
+];
+
+/// The four greens of the original Game Boy.
+pub const GAMEBOY: [Srgba; 4] = [
+    Srgba::rgb(15.0 / 255.0, 56.0 / 255.0, 15.0 / 255.0),
+    Srgba::rgb(48.0 / 255.0, 98.0 / 255.0, 48.0 / 255.0),
+    Srgba::rgb(139.0 / 255.0, 172.0 / 255.0, 15.0 / 255.0),
+    Srgba::rgb(155.0 / 255.0, 188.0 / 255.0, 15.0 / 255.0),
+];
+
+/// A one pixel high palette texture for [`Quantizer::palette`].
+pub fn palette_image(colors: &[Srgba]) -> Image {
+    let data = colors.iter().flat_map(|c| c.to_u8_array()).collect();
+    let mut image = Image::new(
+        Extent3d {
+            width: colors.len() as u32,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.sampler = ImageSampler::nearest();
+    image
+}
+
+/// Draws `texture` through a [`PostChain`].
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+#[uniform(2, PostUniform)]
+pub struct PostMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    pub texture: Option<Handle<Image>>,
+    #[texture(3)]
+    palette: Option<Handle<Image>>,
+    chain: PostChain,
+}
+
+impl PostMaterial {
+    pub fn new(texture: Handle<Image>, chain: &PostChain) -> Self {
+        let mut mat = PostMaterial {
+            texture: Some(texture),
+            palette: None,
+            chain: PostChain(Vec::new()),
+        };
+        mat.set_chain(chain);
+        mat
+    }
+
+    fn set_chain(&mut self, chain: &PostChain) {
+        self.palette = chain
+            .0
+            .iter()
+            .find_map(|s| match &s.pass {
+                PostPass::Quantize(q) => Some(q.palette.clone()),
+                _ => None,
+            })
+            .flatten();
+        self.chain = chain.clone();
+    }
+}
+
+impl Material2d for PostMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "post.wgsl".into()
+    }
+}
+
+// Pass ids, must match `post.wgsl`.
+const PASS_QUANTIZE: u32 = 1;
+const PASS_CRT: u32 = 2;
+const PASS_VIGNETTE: u32 = 3;
+const PASS_FADE: u32 = 4;
+const PASS_ABERRATION: u32 = 5;
+
+/// The GPU side of a [`PostMaterial`], one uniform for WebGL2.
+#[derive(Clone, Default, ShaderType)]
+pub struct PostUniform {
+    order: [UVec4; 2],
+    levels: u32,
+    palette_size: u32,
+    dither: u32,
+    dither_strength: f32,
+    scanlines: f32,
+    curvature: f32,
+    aberration: f32,
+    vignette_strength: f32,
+    vignette_radius: f32,
+    fade_amount: f32,
+    fade_color: Vec4,
+}
+
+impl AsBindGroupShaderType<PostUniform> for PostMaterial {
+    fn as_bind_group_shader_type(&self, images: &RenderAssets<GpuImage>) -> PostUniform {
+        let mut u = PostUniform::default();
+        let mut order = Vec::new();
+
+        let mut seen = Vec::new();
+        for stage in self.chain.0.iter().filter(|s| s.enabled) {
+            let kind = std::mem::discriminant(&stage.pass);
+            if seen.contains(&kind) {
+                continue;
+            }
+            seen.push(kind);
+
+            let id = match &stage.pass {
+                PostPass::Quantize(q) => {
+                    u.levels = q.levels;
+                    u.palette_size = self
+                        .palette
+                        .as_ref()
+                        .and_then(|p| images.get(p))
+                        .map_or(0, |p| p.size.width);
+                    u.dither = q.dither as u32;
+                    u.dither_strength = q.dither_strength;
+                    PASS_QUANTIZE
+                }
+                PostPass::Crt {
+                    scanlines,
+                    curvature,
+                } => {
+                    u.scanlines = *scanlines;
+                    u.curvature = *curvature;
+                    PASS_CRT
+                }
+                PostPass::ChromaticAberration { amount } => {
+                    u.aberration = *amount;
+                    PASS_ABERRATION
+                }
+                PostPass::Vignette { strength, radius } => {
+                    u.vignette_strength = *strength;
+                    u.vignette_radius = *radius;
+                    PASS_VIGNETTE
+                }
+                PostPass::Fade { color, amount } => {
+                    u.fade_color = color.to_linear().to_vec4();
+                    u.fade_amount = *amount;
+                    PASS_FADE
+                }
+            };
+            order.push(id);
+        }
+
+        for (i, id) in order.into_iter().enumerate() {
+            u.order[i / 4][i % 4] = id;
+        }
+        u
+    }
+}
+
+fn follow_quantize_flag(mut chain: ResMut<PostChain>, g_set: Res<GameSettings>) {
+    let on = g_set.contains(GameSettings::COLOR_QUANTIZE);
+    if let Some(stage) = chain.stage_mut(|p| matches!(p, PostPass::Quantize(_)))
+        && stage.enabled != on
+    {
+        stage.enabled = on;
+    }
+}
+
+fn cycle_palette(mut chain: ResMut<PostChain>, presets: Res<PalettePresets>) {
+    let Some(quantizer) = chain.quantizer_mut() else {
+        return;
+    };
+    let next = match &quantizer.palette {
+        None => 0,
+        Some(p) => presets.0.iter().position(|h| h == p).map_or(0, |i| i + 1),
+    };
+    quantizer.palette = presets.0.get(next).cloned();
+}
+
+fn cycle_dither(mut chain: ResMut<PostChain>) {
+    let Some(quantizer) = chain.quantizer_mut() else {
+        return;
+    };
+    quantizer.dither = match quantizer.dither {
+        Dither::Off => Dither::Bayer4,
+        Dither::Bayer4 => Dither::Bayer8,
+        Dither::Bayer8 => Dither::Noise,
+        Dither::Noise => Dither::Off,
+    };
+}
+
+fn toggle_crt(mut chain: ResMut<PostChain>) {
+    if let Some(stage) = chain.stage_mut(|p| matches!(p, PostPass::Crt { .. })) {
+        stage.enabled = !stage.enabled;
+    }
+}
+
+fn sync_chain(chain: Res<PostChain>, mut materials: ResMut<Assets<PostMaterial>>) {
+    for (_, mat) in materials.iter_mut() {
+        mat.set_chain(&chain);
+    }
+}
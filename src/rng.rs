@@ -0,0 +1,121 @@
+use bevy::prelude::*;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use std::ops::{Deref, DerefMut};
+
+/// Pass `--seed <n>` on the command line, or set this variable, to replay the
+/// same randomness. Without either, or with a seed that doesn't parse, one is
+/// picked at random.
+const SEED_FLAG: &str = "--seed";
+const SEED_ENV: &str = "BILL_BOARDS_SEED";
+
+pub fn plugin(app: &mut App) {
+    let seed = match seed_arg(std::env::args()) {
+        Some(value) => parse_seed(SEED_FLAG, &value),
+        None => std::env::var(SEED_ENV)
+            .ok()
+            .and_then(|v| parse_seed(SEED_ENV, &v)),
+    }
+    .unwrap_or_else(rand::random);
+    app.insert_resource(GameRng::new(seed));
+    app.add_systems(Startup, log_seed);
+}
+
+/// The source of all gameplay randomness. The same seed plays out the same way.
+#[derive(Resource)]
+pub struct GameRng {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        GameRng {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// A separate generator derived from the seed, for things that should give
+    /// the same results no matter what else has drawn numbers before them.
+    pub fn fork(&self, stream: u64) -> StdRng {
+        StdRng::seed_from_u64(self.seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+}
+
+impl Deref for GameRng {
+    type Target = StdRng;
+
+    fn deref(&self) -> &Self::Target {
+        &self.rng
+    }
+}
+
+impl DerefMut for GameRng {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.rng
+    }
+}
+
+/// The value given to `--seed`, empty if the flag is last.
+fn seed_arg(args: impl IntoIterator<Item = String>) -> Option<String> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == SEED_FLAG {
+            return Some(args.next().unwrap_or_default());
+        }
+        if let Some(value) = arg
+            .strip_prefix(SEED_FLAG)
+            .and_then(|v| v.strip_prefix('='))
+        {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+fn parse_seed(source: &str, value: &str) -> Option<u64> {
+    let seed = value.parse().ok();
+    if seed.is_none() {
+        warn!("{source} expects an unsigned integer seed, got {value:?}, picking one at random");
+    }
+    seed
+}
+
+fn log_seed(rng: Res<GameRng>) {
+    let seed = rng.seed();
+    info!("Random seed: {seed} (replay with {SEED_FLAG} {seed})");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn seed_args() {
+        assert_eq!(seed_arg(args(&["game", "--seed", "5"])), Some("5".into()));
+        assert_eq!(
+            seed_arg(args(&["game", "--seed=9", "-v"])),
+            Some("9".into())
+        );
+        assert_eq!(seed_arg(args(&["game", "--seed"])), Some(String::new()));
+        assert_eq!(seed_arg(args(&["game", "--seeds", "5"])), None);
+    }
+
+    #[test]
+    fn bad_seeds() {
+        assert_eq!(parse_seed(SEED_FLAG, "42"), Some(42));
+        assert_eq!(parse_seed(SEED_FLAG, &u64::MAX.to_string()), Some(u64::MAX));
+        assert_eq!(parse_seed(SEED_FLAG, ""), None);
+        assert_eq!(parse_seed(SEED_ENV, "-1"), None);
+        assert_eq!(parse_seed(SEED_ENV, "abc"), None);
+    }
+}
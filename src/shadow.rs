@@ -0,0 +1,178 @@
+use crate::GameSettings;
+use crate::billboard::BlendSort;
+use crate::chunk::CubeWorld;
+use crate::cube::{Cube, CubeGrid};
+use crate::flat::{DynamicMaterial, FlatMaterial, MaterialPair, ShadedMaterial, shaded};
+use crate::player::highest_surface;
+use bevy::prelude::*;
+use bevy::render::view::VisibilitySystems;
+use std::f32::consts::FRAC_PI_2;
+
+/// Lift the disc off the surface it lies on to avoid z-fighting.
+const SHADOW_LIFT: f32 = 0.002;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<ShadowDiscMesh>();
+    app.add_observer(despawn_disc);
+    app.add_observer(drop_disc);
+    app.add_systems(
+        PostUpdate,
+        (
+            spawn_discs.before(TransformSystem::TransformPropagate),
+            // Follows the caster's position from this frame, not the last one.
+            place_discs
+                .after(TransformSystem::TransformPropagate)
+                .before(VisibilitySystems::VisibilityPropagate),
+        ),
+    );
+}
+
+/// A dark disc on the surface below the entity, shrinking and fading with height.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct BlobShadow {
+    /// Radius of the disc when the entity touches the surface.
+    pub radius: f32,
+    /// Height above the surface at which the shadow is gone.
+    pub max_height: f32,
+    /// Opacity of the disc when the entity touches the surface.
+    pub opacity: f32,
+}
+
+impl BlobShadow {
+    pub fn new(radius: f32) -> Self {
+        BlobShadow {
+            radius,
+            ..default()
+        }
+    }
+}
+
+impl Default for BlobShadow {
+    fn default() -> Self {
+        BlobShadow {
+            radius: 0.3,
+            max_height: 3.0,
+            opacity: 0.6,
+        }
+    }
+}
+
+#[derive(Component)]
+struct ShadowDisc(Entity);
+
+#[derive(Resource)]
+struct ShadowDiscMesh(Handle<Mesh>);
+
+impl FromWorld for ShadowDiscMesh {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        ShadowDiscMesh(meshes.add(Circle::new(0.5)))
+    }
+}
+
+fn spawn_discs(
+    mut commands: Commands,
+    mut f_mats: ResMut<Assets<FlatMaterial>>,
+    mut s_mats: ResMut<Assets<ShadedMaterial>>,
+    mesh: Res<ShadowDiscMesh>,
+    g_set: Res<GameSettings>,
+    casters: Query<Entity, Added<BlobShadow>>,
+) {
+    for e in &casters {
+        // Each disc fades on its own, so it gets a pair nobody else shares.
+        let pair = MaterialPair {
+            flat: f_mats.add(FlatMaterial {
+                color: LinearRgba::NONE,
+                alpha_mode: AlphaMode::Blend,
+                ..default()
+            }),
+            standard: s_mats.add(shaded(StandardMaterial {
+                base_color: Color::NONE,
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            })),
+        };
+
+        let mut disc = commands.spawn((
+            Mesh3d(mesh.0.clone()),
+            Transform::from_rotation(Quat::from_rotation_x(-FRAC_PI_2)),
+            Visibility::Hidden,
+            DynamicMaterial,
+            // On the ground, so under whatever blended thing stands on it.
+            BlendSort::Bias(-0.5),
+        ));
+        pair.apply(&mut disc, g_set.contains(GameSettings::FLAT));
+
+        let disc = disc.id();
+        commands.entity(e).insert(ShadowDisc(disc));
+    }
+}
+
+fn place_discs(
+    casters: Query<(&BlobShadow, &ShadowDisc, &GlobalTransform)>,
+    mut discs: Query<
+        (
+            &mut Transform,
+            &mut GlobalTransform,
+            &mut Visibility,
+            &MaterialPair,
+        ),
+        (Without<Cube>, Without<BlobShadow>),
+    >,
+    cubes: Query<&Transform, With<Cube>>,
+    world: Res<CubeWorld>,
+    grid: Res<CubeGrid>,
+    mut f_mats: ResMut<Assets<FlatMaterial>>,
+    mut s_mats: ResMut<Assets<ShadedMaterial>>,
+) {
+    for (shadow, disc, g_trans) in &casters {
+        let Ok((mut trans, mut disc_g_trans, mut vis, pair)) = discs.get_mut(disc.0) else {
+            continue;
+        };
+
+        let pos = g_trans.translation();
+        // Same notion of "what am I standing on" as the player's stepping.
+        let near = world.near(&grid, pos.xz(), shadow.radius);
+        let ground = highest_surface(pos.xz(), shadow.radius, pos.y, cubes.iter_many(near))
+            .unwrap_or(0.0)
+            .min(pos.y);
+        let fade = 1.0 - ((pos.y - ground) / shadow.max_height).clamp(0.0, 1.0);
+
+        if fade <= 0.0 {
+            *vis = Visibility::Hidden;
+            continue;
+        }
+        *vis = Visibility::Inherited;
+
+        trans.translation = Vec3::new(pos.x, ground + SHADOW_LIFT, pos.z);
+        trans.scale = Vec3::splat(shadow.radius * 2.0 * (0.5 + 0.5 * fade));
+        // Propagation already ran this frame. Discs have no parent.
+        *disc_g_trans = GlobalTransform::from(*trans);
+
+        // Both halves, so switching modes shows the same fade.
+        let color = Color::BLACK.with_alpha(shadow.opacity * fade);
+        if let Some(mat) = f_mats.get_mut(pair.flat.id()) {
+            mat.color = color.into();
+        }
+        if let Some(mat) = s_mats.get_mut(pair.standard.id()) {
+            mat.base.base_color = color;
+        }
+    }
+}
+
+fn despawn_disc(
+    trigger: Trigger<OnRemove, ShadowDisc>,
+    mut commands: Commands,
+    discs: Query<&ShadowDisc>,
+) {
+    let Ok(disc) = discs.get(trigger.target()) else {
+        return;
+    };
+    commands.entity(disc.0).despawn();
+}
+
+/// Taking the shadow off a caster that stays around also takes its disc.
+fn drop_disc(trigger: Trigger<OnRemove, BlobShadow>, mut commands: Commands) {
+    commands.entity(trigger.target()).try_remove::<ShadowDisc>();
+}
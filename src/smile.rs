@@ -1,4 +1,6 @@
 use super::*;
+use crate::particles::ParticleEmitter;
+use crate::shadow::BlobShadow;
 use crate::sinphase::SinPhase;
 use bevy::{image::*, prelude::*};
 
@@ -28,14 +30,26 @@ fn setup(
     commands.spawn((
         billboard::Billboard::default(),
         Mesh3d(meshes.add(Plane3d::new(Vec3::Z, Vec2::new(0.5, 0.5)))),
+        crate::interact::Interactable::quad(Vec2::ONE),
         MeshMaterial3d(materials.add(FlatMaterial {
             texture: Some(bill_smile.clone()),
             color: Color::srgb_u8(0, 255, 0).into(),
-            alpha_mode: AlphaMode::Blend,
+            alpha_mode: AlphaMode::Mask(0.5),
+            ..default()
         })),
         Transform::from_translation(CENTER_BILL_POS),
         Smile,
         SinPhase::default(),
+        BlobShadow::new(0.25),
+        ParticleEmitter {
+            rate: 16.0,
+            lifetime: 0.6,
+            speed: (0.5, 1.0),
+            gravity: Vec3::new(0.0, -2.0, 0.0),
+            size: (0.06, 0.0),
+            color: (Color::srgb_u8(0, 255, 0), Color::srgba_u8(255, 255, 0, 0)),
+            ..default()
+        },
     ));
 }
 
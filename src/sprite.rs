@@ -0,0 +1,437 @@
+use crate::MainCamera;
+use crate::billboard::{Billboard, BillboardMode};
+use crate::flat::{
+    DynamicMaterial, FlatMaterial, MaterialCache, ShadedMaterial, own_material,
+    uv_rect_to_transform,
+};
+use crate::interact::Interactable;
+use bevy::image::{ImageLoaderSettings, ImageSampler};
+use bevy::prelude::*;
+use std::f32::consts::TAU;
+
+pub fn plugin(app: &mut App) {
+    app.add_event::<FlipbookFinished>();
+    app.add_systems(Startup, setup);
+    app.add_systems(Update, (play_flipbooks, despawn_finished).chain());
+    app.add_systems(PostUpdate, (pick_directions, apply_sheets).chain());
+}
+
+/// A grid of equally sized cells in the entity's texture.
+///
+/// The cell is written into the entity's material. A cached material is
+/// swapped for a copy first, but any other material must not be shared.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct SpriteSheet {
+    columns: u32,
+    rows: u32,
+    /// Column (x) and row (y) of the cell to show.
+    pub cell: UVec2,
+}
+
+impl Default for SpriteSheet {
+    fn default() -> Self {
+        SpriteSheet::new(1, 1)
+    }
+}
+
+impl SpriteSheet {
+    pub fn new(columns: u32, rows: u32) -> Self {
+        SpriteSheet {
+            columns: columns.max(1),
+            rows: rows.max(1),
+            cell: UVec2::ZERO,
+        }
+    }
+
+    /// UV offset (xy) and scale (zw) of the current cell.
+    pub fn uv_rect(&self) -> Vec4 {
+        let size = Vec2::new(1.0 / self.columns as f32, 1.0 / self.rows as f32);
+        let cell = self.cell.min(UVec2::new(self.columns - 1, self.rows - 1));
+        Vec4::new(
+            cell.x as f32 * size.x,
+            cell.y as f32 * size.y,
+            size.x,
+            size.y,
+        )
+    }
+}
+
+/// Shows a different sheet row depending on where the camera stands relative to
+/// the entity's facing, like classic 8-direction sprites.
+///
+/// Row 0 is the front view, and the following rows step counter-clockwise
+/// around the sprite when seen from above.
+#[derive(Component, Clone, Copy, Debug)]
+#[require(SpriteSheet)]
+pub struct DirectionalSprite {
+    /// Logical facing of the entity around Y, in radians. 0 faces -Z.
+    pub yaw: f32,
+    pub directions: u32,
+}
+
+impl Default for DirectionalSprite {
+    fn default() -> Self {
+        DirectionalSprite {
+            yaw: 0.0,
+            directions: 8,
+        }
+    }
+}
+
+impl DirectionalSprite {
+    /// Sheet row to show for a viewer at `to_viewer` from the sprite.
+    pub fn direction_to(&self, to_viewer: Vec3) -> u32 {
+        let directions = self.directions.max(1);
+        let viewer_yaw = f32::atan2(-to_viewer.x, -to_viewer.z);
+        let step = TAU / directions as f32;
+        let relative = (viewer_yaw - self.yaw).rem_euclid(TAU);
+        (relative / step).round() as u32 % directions
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FlipbookMode {
+    #[default]
+    Loop,
+    /// Play forwards, then backwards, forever.
+    PingPong,
+    /// Play once, stop on the last frame and send [`FlipbookFinished`].
+    Once,
+}
+
+/// Steps through the cells of the entity's [`SpriteSheet`].
+///
+/// Frames are read row by row. With a [`DirectionalSprite`] the row is owned
+/// by the direction, so frames run along its columns instead.
+#[derive(Component, Clone, Debug)]
+#[require(SpriteSheet)]
+pub struct Flipbook {
+    frames: u32,
+    pub mode: FlipbookMode,
+    timer: Timer,
+    step: u32,
+    finished: bool,
+}
+
+impl Flipbook {
+    /// `fps` is kept between 0.01 and 1000; NaN counts as the minimum.
+    pub fn new(frames: u32, fps: f32, mode: FlipbookMode) -> Self {
+        let fps = if fps.is_nan() {
+            0.01
+        } else {
+            fps.clamp(0.01, 1000.0)
+        };
+        Flipbook {
+            frames: frames.max(1),
+            mode,
+            timer: Timer::from_seconds(1.0 / fps, TimerMode::Repeating),
+            step: 0,
+            finished: false,
+        }
+    }
+
+    /// The frame currently shown.
+    pub fn frame(&self) -> u32 {
+        let last = self.frames - 1;
+        match self.mode {
+            FlipbookMode::Loop => self.step % self.frames,
+            FlipbookMode::PingPong if last == 0 => 0,
+            FlipbookMode::PingPong => {
+                let p = self.step % (2 * last);
+                if p <= last { p } else { 2 * last - p }
+            }
+            FlipbookMode::Once => self.step.min(last),
+        }
+    }
+
+    /// Move on by `steps` frames. True when a [`FlipbookMode::Once`]
+    /// animation has just shown its last frame for a full frame time.
+    fn advance(&mut self, steps: u32) -> bool {
+        self.step = self.step.wrapping_add(steps);
+        if self.mode == FlipbookMode::Once && !self.finished && self.step >= self.frames {
+            self.finished = true;
+            return true;
+        }
+        false
+    }
+}
+
+/// Sent when a [`FlipbookMode::Once`] animation is done showing its last frame.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct FlipbookFinished(pub Entity);
+
+/// Despawn the entity once its [`FlipbookMode::Once`] animation is done.
+#[derive(Component, Default)]
+pub struct DespawnOnFinish;
+
+/// Two demo boards on the 2x2 `grid_set.png`: one picking its row by viewing
+/// angle while bouncing along the columns, one playing through once and going away.
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<FlatMaterial>>,
+    assets: Res<AssetServer>,
+) {
+    let sheet: Handle<Image> = assets.load_with_settings("grid_set.png", |s: &mut _| {
+        *s = ImageLoaderSettings {
+            sampler: ImageSampler::nearest(),
+            ..default()
+        }
+    });
+    let quad = meshes.add(Plane3d::new(Vec3::Z, Vec2::splat(0.5)));
+
+    // Each board writes its cell into its material, so they get one each.
+    commands.spawn((
+        Billboard::with_mode(BillboardMode::Cylindrical),
+        Mesh3d(quad.clone()),
+        Interactable::quad(Vec2::ONE),
+        MeshMaterial3d(materials.add(FlatMaterial {
+            texture: Some(sheet.clone()),
+            ..default()
+        })),
+        DynamicMaterial,
+        Transform::from_xyz(-1.5, 0.5, -1.5).with_scale(Vec3::splat(0.5)),
+        DirectionalSprite {
+            directions: 2,
+            ..default()
+        },
+        SpriteSheet::new(2, 2),
+        Flipbook::new(2, 4.0, FlipbookMode::PingPong),
+    ));
+    commands.spawn((
+        Billboard::with_mode(BillboardMode::PointAt),
+        Mesh3d(quad),
+        Interactable::quad(Vec2::ONE),
+        MeshMaterial3d(materials.add(FlatMaterial {
+            texture: Some(sheet),
+            ..default()
+        })),
+        DynamicMaterial,
+        Transform::from_xyz(-1.5, 1.25, -1.5).with_scale(Vec3::splat(0.25)),
+        SpriteSheet::new(2, 2),
+        Flipbook::new(4, 2.0, FlipbookMode::Once),
+        DespawnOnFinish,
+    ));
+}
+
+fn play_flipbooks(
+    mut books: Query<(
+        Entity,
+        &mut Flipbook,
+        &mut SpriteSheet,
+        Has<DirectionalSprite>,
+    )>,
+    mut finished: EventWriter<FlipbookFinished>,
+    time: Res<Time>,
+) {
+    for (e, mut book, mut sheet, directional) in &mut books {
+        if book.finished {
+            continue;
+        }
+
+        book.timer.tick(time.delta());
+        let steps = book.timer.times_finished_this_tick();
+        if steps == 0 {
+            continue;
+        }
+        if book.advance(steps) {
+            finished.write(FlipbookFinished(e));
+        }
+
+        let frame = book.frame();
+        let cell = if directional {
+            UVec2::new(frame % sheet.columns, sheet.cell.y)
+        } else {
+            UVec2::new(frame % sheet.columns, frame / sheet.columns)
+        };
+        if sheet.cell != cell {
+            sheet.cell = cell;
+        }
+    }
+}
+
+fn despawn_finished(
+    mut commands: Commands,
+    mut finished: EventReader<FlipbookFinished>,
+    one_shots: Query<(), With<DespawnOnFinish>>,
+) {
+    for FlipbookFinished(e) in finished.read() {
+        if one_shots.contains(*e) {
+            commands.entity(*e).despawn();
+        }
+    }
+}
+
+/// Rows are picked for the first `MainCamera`, so other views see the sprite
+/// from that camera's angle.
+fn pick_directions(
+    mut sprites: Query<(&mut SpriteSheet, &DirectionalSprite, &GlobalTransform)>,
+    cam: Query<&GlobalTransform, With<MainCamera>>,
+) {
+    let Some(cam_transform) = cam.iter().next() else {
+        return;
+    };
+
+    for (mut sheet, dir, transform) in &mut sprites {
+        let to_cam = cam_transform.translation() - transform.translation();
+        let row = dir.direction_to(to_cam);
+        if sheet.cell.y != row {
+            sheet.cell.y = row;
+        }
+    }
+}
+
+fn apply_sheets(
+    mut commands: Commands,
+    mut f_mats: ResMut<Assets<FlatMaterial>>,
+    mut s_mats: ResMut<Assets<ShadedMaterial>>,
+    cache: Res<MaterialCache>,
+    sheets: Query<
+        (
+            Entity,
+            &SpriteSheet,
+            Option<&MeshMaterial3d<FlatMaterial>>,
+            Option<&MeshMaterial3d<ShadedMaterial>>,
+        ),
+        Or<(
+            Changed<SpriteSheet>,
+            Changed<MeshMaterial3d<FlatMaterial>>,
+            Changed<MeshMaterial3d<ShadedMaterial>>,
+        )>,
+    >,
+) {
+    for (e, sheet, f_mat, s_mat) in sheets {
+        let uv_rect = sheet.uv_rect();
+        let uv_transform = uv_rect_to_transform(uv_rect);
+        if let Some(m) = f_mat
+            && f_mats.get(m).is_some_and(|mat| mat.uv_rect != uv_rect)
+            && let Some(mat) = own_material(&mut commands, &cache, e, m, &mut f_mats)
+        {
+            mat.uv_rect = uv_rect;
+        }
+        if let Some(m) = s_mat
+            && s_mats
+                .get(m)
+                .is_some_and(|mat| mat.base.uv_transform != uv_transform)
+            && let Some(mat) = own_material(&mut commands, &cache, e, m, &mut s_mats)
+        {
+            mat.base.uv_transform = uv_transform;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite(directions: u32) -> DirectionalSprite {
+        DirectionalSprite {
+            yaw: 0.0,
+            directions,
+        }
+    }
+
+    fn book(frames: u32, mode: FlipbookMode, step: u32) -> Flipbook {
+        Flipbook {
+            step,
+            ..Flipbook::new(frames, 1.0, mode)
+        }
+    }
+
+    #[test]
+    fn flipbook_frames() {
+        let frames = |mode| {
+            (0..8)
+                .map(|step| book(4, mode, step).frame())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(frames(FlipbookMode::Loop), [0, 1, 2, 3, 0, 1, 2, 3]);
+        assert_eq!(frames(FlipbookMode::PingPong), [0, 1, 2, 3, 2, 1, 0, 1]);
+        assert_eq!(frames(FlipbookMode::Once), [0, 1, 2, 3, 3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn flipbook_single_frame() {
+        for mode in [
+            FlipbookMode::Loop,
+            FlipbookMode::PingPong,
+            FlipbookMode::Once,
+        ] {
+            assert_eq!(book(1, mode, 5).frame(), 0);
+            assert_eq!(book(0, mode, 5).frame(), 0);
+        }
+    }
+
+    #[test]
+    fn once_finishes_after_the_last_frame() {
+        let mut book = book(3, FlipbookMode::Once, 0);
+        assert!(!book.advance(2));
+        assert_eq!(book.frame(), 2);
+        assert!(book.advance(1));
+        assert!(!book.advance(1));
+        assert_eq!(book.frame(), 2);
+    }
+
+    #[test]
+    fn flipbook_bad_fps() {
+        for fps in [0.0, -3.0, f32::NAN, f32::INFINITY] {
+            let book = Flipbook::new(2, fps, FlipbookMode::Loop);
+            assert!(book.timer.duration().as_secs_f32() > 0.0, "{fps}");
+        }
+    }
+
+    #[test]
+    fn empty_sheet() {
+        let sheet = SpriteSheet::new(0, 0);
+        assert_eq!(sheet.uv_rect(), Vec4::new(0.0, 0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn four_directions() {
+        let dir = sprite(4);
+        // The sprite faces -Z, so a viewer out on -Z sees its front.
+        assert_eq!(dir.direction_to(Vec3::NEG_Z), 0);
+        assert_eq!(dir.direction_to(Vec3::NEG_X), 1);
+        assert_eq!(dir.direction_to(Vec3::Z), 2);
+        assert_eq!(dir.direction_to(Vec3::X), 3);
+    }
+
+    #[test]
+    fn eight_directions() {
+        let dir = sprite(8);
+        let around = [
+            Vec3::NEG_Z,
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::NEG_X,
+            Vec3::new(-1.0, 0.0, 1.0),
+            Vec3::Z,
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::X,
+            Vec3::new(1.0, 0.0, -1.0),
+        ];
+        for (row, to_viewer) in around.into_iter().enumerate() {
+            assert_eq!(dir.direction_to(to_viewer), row as u32, "{to_viewer}");
+        }
+    }
+
+    #[test]
+    fn sectors_round_to_nearest() {
+        let dir = sprite(4);
+        // Just short of halfway between front and left stays front.
+        let to_viewer = Quat::from_rotation_y(0.7) * Vec3::NEG_Z;
+        assert_eq!(dir.direction_to(to_viewer), 0);
+        // Wrapping around past the last row lands back on the front.
+        let to_viewer = Quat::from_rotation_y(-0.7) * Vec3::NEG_Z;
+        assert_eq!(dir.direction_to(to_viewer), 0);
+    }
+
+    #[test]
+    fn follows_the_sprite_yaw() {
+        let dir = DirectionalSprite {
+            yaw: std::f32::consts::FRAC_PI_2,
+            directions: 4,
+        };
+        assert_eq!(dir.direction_to(Vec3::NEG_X), 0);
+        assert_eq!(dir.direction_to(Vec3::NEG_Z), 3);
+    }
+}
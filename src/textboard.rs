@@ -0,0 +1,393 @@
+use crate::billboard::{Billboard, BillboardMode, BlendSort, ScreenSize};
+use crate::display::render_image;
+use crate::flat::{DynamicMaterial, FlatMaterial};
+use crate::interact::Interactable;
+use bevy::prelude::*;
+use bevy::render::camera::RenderTarget;
+use bevy::render::render_resource::Extent3d;
+use bevy::render::view::RenderLayers;
+use bevy::text::FontSmoothing;
+use bevy::transform::TransformSystem;
+
+/// Render layers below this are used by the game cameras.
+const FIRST_TEXT_LAYER: usize = 8;
+
+/// Offsets (in pixels) of the copies drawn behind the text to fake an outline.
+const OUTLINE_DIRS: [Vec2; 8] = [
+    Vec2::new(-1.0, -1.0),
+    Vec2::new(0.0, -1.0),
+    Vec2::new(1.0, -1.0),
+    Vec2::new(-1.0, 0.0),
+    Vec2::new(1.0, 0.0),
+    Vec2::new(-1.0, 1.0),
+    Vec2::new(0.0, 1.0),
+    Vec2::new(1.0, 1.0),
+];
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<TextLayers>();
+    app.add_systems(Startup, setup);
+    app.add_observer(despawn_canvas);
+    app.add_systems(Update, (build_text_boards, update_text_boards).chain());
+    app.add_systems(
+        PostUpdate,
+        (
+            settle_text_cameras,
+            follow_entities.before(TransformSystem::TransformPropagate),
+        ),
+    );
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TextOutline {
+    pub color: Color,
+    /// Outline thickness in texture pixels.
+    pub width: f32,
+}
+
+/// A billboard showing text drawn into its own texture.
+///
+/// The texture is only redrawn when this component changes. Changing the
+/// resolution or height resizes the texture and the quad.
+#[derive(Component, Clone, Debug)]
+#[require(Billboard, DynamicMaterial)]
+pub struct TextBillboard {
+    pub text: String,
+    pub font: Handle<Font>,
+    /// Font size in texture pixels.
+    pub size: f32,
+    pub color: Color,
+    pub outline: Option<TextOutline>,
+    /// Size of the texture the text is drawn into.
+    pub resolution: UVec2,
+    /// Height of the board in world units. The width follows the resolution.
+    pub height: f32,
+}
+
+impl Default for TextBillboard {
+    fn default() -> Self {
+        TextBillboard {
+            text: String::new(),
+            font: default(),
+            size: 16.0,
+            color: Color::WHITE,
+            outline: Some(TextOutline {
+                color: Color::BLACK,
+                width: 1.0,
+            }),
+            resolution: UVec2::new(128, 32),
+            height: 0.25,
+        }
+    }
+}
+
+impl TextBillboard {
+    pub fn new(text: impl Into<String>) -> Self {
+        TextBillboard {
+            text: text.into(),
+            ..default()
+        }
+    }
+}
+
+/// Keeps the entity `offset` away from `target`, without taking on its
+/// rotation and scale the way a child would.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct FollowEntity {
+    pub target: Entity,
+    pub offset: Vec3,
+}
+
+/// A label floating `height` above `target`, kept at one texture pixel per
+/// screen pixel whatever the distance.
+pub fn name_tag(name: &str, target: Entity, height: f32) -> impl Bundle {
+    let board = TextBillboard::new(name);
+    let screen_size = ScreenSize::new(board.resolution.y as f32 / board.height);
+    (
+        board,
+        Billboard::with_mode(BillboardMode::Cylindrical).with_screen_size(screen_size),
+        // A label, so it goes over other blended boards around it.
+        BlendSort::Layer(1),
+        FollowEntity {
+            target,
+            offset: Vec3::Y * height,
+        },
+    )
+}
+
+/// The hidden camera and text entities drawing a [`TextBillboard`].
+#[derive(Component)]
+struct TextCanvas {
+    layer: usize,
+    camera: Entity,
+    image: Handle<Image>,
+    fill: Entity,
+    /// Empty when the board has no outline.
+    outline: Vec<Entity>,
+    /// Texture size and board height the quad was built for.
+    resolution: UVec2,
+    height: f32,
+    /// The texture needs drawing this frame.
+    dirty: bool,
+}
+
+/// Render layers for the text boards. `RenderLayers` grows as needed, so
+/// there is no cap, but freed layers are reused to keep the masks short.
+#[derive(Resource)]
+struct TextLayers {
+    free: Vec<usize>,
+    next: usize,
+}
+
+impl Default for TextLayers {
+    fn default() -> Self {
+        TextLayers {
+            free: Vec::new(),
+            next: FIRST_TEXT_LAYER,
+        }
+    }
+}
+
+impl TextLayers {
+    fn take(&mut self) -> usize {
+        self.free.pop().unwrap_or_else(|| {
+            self.next += 1;
+            self.next - 1
+        })
+    }
+}
+
+fn setup(mut commands: Commands) {
+    let board = TextBillboard::new("bill boards");
+    // One texture pixel per screen pixel, whatever the distance.
+    let screen_size = ScreenSize::new(board.resolution.y as f32 / board.height);
+    commands.spawn((
+        board,
+        Billboard::with_mode(BillboardMode::Cylindrical).with_screen_size(screen_size),
+        // A label, so it goes over other blended boards around it.
+        BlendSort::Layer(1),
+        Transform::from_xyz(-1.0, 1.25, -1.0),
+    ));
+}
+
+fn texture_size(board: &TextBillboard) -> Extent3d {
+    Extent3d {
+        width: board.resolution.x.max(1),
+        height: board.resolution.y.max(1),
+        depth_or_array_layers: 1,
+    }
+}
+
+/// The quad showing the texture, `height` tall and as wide as its aspect.
+fn quad_size(board: &TextBillboard) -> Vec2 {
+    let size = texture_size(board);
+    let aspect = size.width as f32 / size.height as f32;
+    Vec2::new(board.height * aspect, board.height)
+}
+
+/// Copies of the text behind the fill, one per outline direction.
+fn spawn_outline(
+    commands: &mut Commands,
+    board: &TextBillboard,
+    outline: TextOutline,
+    layer: &RenderLayers,
+) -> Vec<Entity> {
+    OUTLINE_DIRS
+        .iter()
+        .map(|dir| {
+            commands
+                .spawn((
+                    text_bundle(board, outline.color, *dir * outline.width, 0.0),
+                    layer.clone(),
+                ))
+                .id()
+        })
+        .collect()
+}
+
+fn text_bundle(board: &TextBillboard, color: Color, offset: Vec2, z: f32) -> impl Bundle {
+    (
+        Text2d::new(board.text.clone()),
+        TextFont {
+            font: board.font.clone(),
+            font_size: board.size,
+            font_smoothing: FontSmoothing::None,
+            ..default()
+        },
+        TextColor(color),
+        Transform::from_translation(offset.extend(z)),
+    )
+}
+
+fn build_text_boards(
+    mut commands: Commands,
+    mut layers: ResMut<TextLayers>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<FlatMaterial>>,
+    boards: Query<(Entity, &TextBillboard), Added<TextBillboard>>,
+) {
+    for (e, board) in &boards {
+        let layer_id = layers.take();
+        let target = images.add(render_image(texture_size(board)));
+
+        let layer = RenderLayers::layer(layer_id);
+
+        // Drawn before the 3D camera so the texture is fresh the same frame.
+        let camera = commands
+            .spawn((
+                Camera2d,
+                Camera {
+                    order: -1,
+                    clear_color: ClearColorConfig::Custom(Color::NONE),
+                    target: RenderTarget::Image(target.clone().into()),
+                    ..default()
+                },
+                Msaa::Off,
+                layer.clone(),
+            ))
+            .id();
+
+        let fill = commands
+            .spawn((
+                text_bundle(board, board.color, Vec2::ZERO, 1.0),
+                layer.clone(),
+            ))
+            .id();
+
+        let outline = board
+            .outline
+            .map(|o| spawn_outline(&mut commands, board, o, &layer))
+            .unwrap_or_default();
+
+        let quad = quad_size(board);
+
+        commands.entity(e).insert((
+            Mesh3d(meshes.add(Rectangle::from_size(quad))),
+            MeshMaterial3d(materials.add(FlatMaterial {
+                texture: Some(target.clone()),
+                alpha_mode: AlphaMode::Blend,
+                ..default()
+            })),
+            Interactable::quad(quad),
+            TextCanvas {
+                layer: layer_id,
+                camera,
+                image: target,
+                fill,
+                outline,
+                resolution: board.resolution,
+                height: board.height,
+                dirty: true,
+            },
+        ));
+    }
+}
+
+fn update_text_boards(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut boards: Query<(Entity, &TextBillboard, &mut TextCanvas), Changed<TextBillboard>>,
+    mut texts: Query<(&mut Text2d, &mut TextFont, &mut TextColor, &mut Transform)>,
+) {
+    for (e, board, mut canvas) in &mut boards {
+        canvas.dirty = true;
+
+        if canvas.resolution != board.resolution
+            && let Some(image) = images.get_mut(&canvas.image)
+        {
+            image.resize(texture_size(board));
+        }
+        if canvas.resolution != board.resolution || canvas.height != board.height {
+            let quad = quad_size(board);
+            commands.entity(e).insert((
+                Mesh3d(meshes.add(Rectangle::from_size(quad))),
+                Interactable::quad(quad),
+            ));
+            canvas.resolution = board.resolution;
+            canvas.height = board.height;
+        }
+
+        match board.outline {
+            Some(o) if canvas.outline.is_empty() => {
+                let layer = RenderLayers::layer(canvas.layer);
+                canvas.outline = spawn_outline(&mut commands, board, o, &layer);
+            }
+            None => {
+                for e in canvas.outline.drain(..) {
+                    commands.entity(e).despawn();
+                }
+            }
+            _ => {}
+        }
+
+        let mut parts = vec![(canvas.fill, board.color, Vec2::ZERO)];
+        if let Some(o) = board.outline {
+            for (e, dir) in canvas.outline.iter().zip(OUTLINE_DIRS) {
+                parts.push((*e, o.color, dir * o.width));
+            }
+        }
+
+        for (e, color, offset) in parts {
+            let Ok((mut text, mut font, mut tint, mut transform)) = texts.get_mut(e) else {
+                continue;
+            };
+            if text.0 != board.text {
+                text.0.clone_from(&board.text);
+            }
+            font.font = board.font.clone();
+            font.font_size = board.size;
+            tint.0 = color;
+            transform.translation = offset.extend(transform.translation.z);
+        }
+    }
+}
+
+/// Only keep a text camera rendering until its font is loaded and drawn once.
+fn settle_text_cameras(
+    mut boards: Query<(&TextBillboard, &mut TextCanvas)>,
+    mut cameras: Query<&mut Camera>,
+    assets: Res<AssetServer>,
+) {
+    for (board, mut canvas) in &mut boards {
+        let Ok(mut camera) = cameras.get_mut(canvas.camera) else {
+            continue;
+        };
+        camera.is_active = canvas.dirty;
+
+        let font_ready =
+            board.font == Handle::default() || assets.is_loaded_with_dependencies(board.font.id());
+        if font_ready {
+            canvas.dirty = false;
+        }
+    }
+}
+
+fn despawn_canvas(
+    trigger: Trigger<OnRemove, TextCanvas>,
+    mut commands: Commands,
+    mut layers: ResMut<TextLayers>,
+    canvases: Query<&TextCanvas>,
+) {
+    let Ok(canvas) = canvases.get(trigger.target()) else {
+        return;
+    };
+    layers.free.push(canvas.layer);
+    commands.entity(canvas.camera).despawn();
+    commands.entity(canvas.fill).despawn();
+    for e in &canvas.outline {
+        commands.entity(*e).despawn();
+    }
+}
+
+fn follow_entities(
+    mut followers: Query<(&mut Transform, &FollowEntity)>,
+    targets: Query<&Transform, Without<FollowEntity>>,
+) {
+    for (mut transform, follow) in &mut followers {
+        if let Ok(target) = targets.get(follow.target) {
+            transform.translation = target.translation + follow.offset;
+        }
+    }
+}
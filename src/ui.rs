@@ -1,4 +1,5 @@
 use crate::display::RenderTex;
+use crate::interact;
 use bevy::prelude::*;
 use bevy::render::camera::RenderTarget;
 use bevy::render::view::RenderLayers;
@@ -6,8 +7,18 @@ use bevy::render::view::RenderLayers;
 #[derive(Component)]
 struct UICamera;
 
+/// The dot in the middle of the screen. Lights up over anything pickable.
+#[derive(Component, Default)]
+struct Crosshair {
+    target: Option<Entity>,
+}
+
+const CROSSHAIR_IDLE: Color = Color::srgb(1.0, 0.0, 0.0);
+const CROSSHAIR_HOVER: Color = Color::srgb(1.0, 1.0, 0.0);
+
 pub fn plugin(app: &mut App) {
     app.add_systems(Startup, setup);
+    app.add_systems(Update, light_crosshair.after(interact::pick));
 }
 
 fn setup(
@@ -31,8 +42,38 @@ fn setup(
     commands.spawn((
         Mesh2d::from(meshes.add(Rectangle::default())),
         MeshMaterial2d::from(materials.add(ColorMaterial {
-            color: Color::srgba_u8(255, 0, 0, 255),
+            color: CROSSHAIR_IDLE,
             ..default()
         })),
+        Crosshair::default(),
     ));
 }
+
+fn light_crosshair(
+    mut starts: EventReader<interact::HoverStart>,
+    mut ends: EventReader<interact::HoverEnd>,
+    mut crosshairs: Query<(&mut Crosshair, &MeshMaterial2d<ColorMaterial>)>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let Ok((mut crosshair, material)) = crosshairs.single_mut() else {
+        return;
+    };
+    // A hover moving between two entities ends the old one first.
+    for interact::HoverEnd(e) in ends.read() {
+        if crosshair.target == Some(*e) {
+            crosshair.target = None;
+        }
+    }
+    for interact::HoverStart(e) in starts.read() {
+        crosshair.target = Some(*e);
+    }
+
+    if crosshair.is_changed()
+        && let Some(material) = materials.get_mut(material.id())
+    {
+        material.color = match crosshair.target {
+            Some(_) => CROSSHAIR_HOVER,
+            None => CROSSHAIR_IDLE,
+        };
+    }
+}
@@ -11,11 +11,17 @@ pub fn plugin(app: &mut App) {
 struct Wyatt;
 
 fn setup(mut commands: Commands, assets: Res<AssetServer>) {
-    commands.spawn((
-        SceneRoot(assets.load(GltfAssetLabel::Scene(0).from_asset("wyatt.glb"))),
-        Wyatt,
-        Transform::from_scale(Vec3::splat(1.0 / 7.0)).with_translation(Vec3::new(2.0, 0.7, 2.0)),
-    ));
+    let npc = commands
+        .spawn((
+            SceneRoot(assets.load(GltfAssetLabel::Scene(0).from_asset("wyatt.glb"))),
+            Wyatt,
+            crate::interact::PickableScene,
+            crate::shadow::BlobShadow::new(0.35),
+            Transform::from_scale(Vec3::splat(1.0 / 7.0))
+                .with_translation(Vec3::new(2.0, 0.7, 2.0)),
+        ))
+        .id();
+    commands.spawn(crate::textboard::name_tag("Wyatt", npc, 0.5));
 }
 
 fn update(